@@ -0,0 +1,52 @@
+//! Compare the two ways the shell starts external commands, `fork`
+//! and `exec` versus `posix_spawn`, in a shell using a lot of memory,
+//! like one with a long history or big variables. The shell uses
+//! `posix_spawn` where it can, `STSH_FORCE_FORK` makes it always fork.
+//! Run with `cargo bench --bench spawn`.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{self, Command};
+use std::time::Duration;
+
+const RUNS: u32 = 200;
+const BALLAST_SIZE: usize = 1 << 28;
+
+/// Run the script at `path` with the shell built by cargo and return
+/// the average duration of a command, computed from the timestamps in
+/// nanoseconds the script prints before and after its commands.
+fn measure(path: &Path, force_fork: bool) -> Duration {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_stsh-rs"));
+    command.arg(path);
+    if force_fork {
+        command.env("STSH_FORCE_FORK", "1");
+    }
+
+    let output = command.output().unwrap();
+    assert!(output.status.success());
+    let timestamps: Vec<u64> = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(|timestamp| timestamp.parse().unwrap())
+        .collect();
+
+    Duration::from_nanos(timestamps[1] - timestamps[0]) / RUNS
+}
+
+fn main() {
+    let path = env::temp_dir().join(format!("stsh-bench-spawn-{}", process::id()));
+    // a variable the shell keeps in memory, such that fork has to
+    // copy the page tables for it
+    let mut script = format!(
+        "BALLAST=$(head -c {} /dev/zero | tr '\\0' x)\nSTART=$(date +%s%N)\n",
+        BALLAST_SIZE
+    );
+    script.push_str(&"/bin/true\n".repeat(RUNS as usize));
+    script.push_str("echo $START $(date +%s%N)\n");
+    fs::write(&path, script).unwrap();
+
+    println!("fork: {:?} per command", measure(&path, true));
+    println!("posix_spawn: {:?} per command", measure(&path, false));
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,1261 @@
+//! Builtin commands
+//!
+//! Builtins are executed within the shell process, such that they
+//! can change the state of the shell.
+
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use libc::{__errno_location, c_int, pid_t, strerror};
+
+use crate::conditional::{self, ConditionalError};
+use crate::execution::exit_shell;
+use crate::expansion::quote;
+use crate::hash::{is_executable, search_path};
+use crate::script::execute_script;
+use crate::shell::Shell;
+use crate::sig::{signal_name, signal_number, take_trapped_signal, SIGNALS};
+use crate::trap::{handle_pending_signals, run_trap, set_trap, Condition};
+
+/// A builtin gets the expanded command line, including its own name,
+/// and returns the exit status.
+pub(crate) type Builtin = fn(&mut Shell, &[String]) -> c_int;
+
+pub(crate) fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "." | "source" => Some(source),
+        "[" | "test" => Some(test),
+        "alias" => Some(alias),
+        "command" => Some(command),
+        "disown" => Some(disown),
+        "exit" => Some(exit),
+        "hash" => Some(hash),
+        "kill" => Some(kill),
+        "return" => Some(return_builtin),
+        "set" => Some(set),
+        "shift" => Some(shift),
+        "trap" => Some(trap),
+        "type" => Some(type_builtin),
+        "unalias" => Some(unalias),
+        "wait" => Some(wait),
+        "which" => Some(which),
+        _ => None,
+    }
+}
+
+/// Whether `name` is one of the special builtins of POSIX, whose
+/// variable assignments stay in effect after they finish. Only the
+/// special builtins the shell has are listed.
+pub(crate) fn is_special(name: &str) -> bool {
+    matches!(name, "." | "exit" | "return" | "set" | "shift" | "trap")
+}
+
+/// Find the file to be sourced. A name without a slash is searched in
+/// `$PATH` first, falling back to the current directory.
+fn find_source_file(shell: &Shell, name: &str) -> PathBuf {
+    if !name.contains('/') {
+        if let Some(path) = shell.var("PATH") {
+            for dir in env::split_paths(path) {
+                let candidate = dir.join(name);
+                if candidate.is_file() {
+                    return candidate;
+                }
+            }
+        }
+    }
+
+    PathBuf::from(name)
+}
+
+/// `source file [args]` and `. file [args]`
+///
+/// Execute the commands in `file` within the current shell. If
+/// arguments are given, they replace the positional parameters while
+/// the file is executed.
+fn source(shell: &mut Shell, argv: &[String]) -> c_int {
+    let name = match argv.get(1) {
+        Some(name) => name,
+        None => {
+            eprintln!("stsh: {}: filename argument required", argv[0]);
+            return 2;
+        }
+    };
+
+    let path = find_source_file(shell, name);
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("stsh: {}: {}", name, e);
+            return 1;
+        }
+    };
+
+    let saved_positional = if argv.len() > 2 {
+        Some(mem::replace(&mut shell.positional, argv[2..].to_vec()))
+    } else {
+        None
+    };
+
+    shell.source_depth += 1;
+    let mut status = execute_script(shell, &path.to_string_lossy(), &source);
+    shell.source_depth -= 1;
+
+    if let Some(return_status) = shell.pending_return.take() {
+        status = return_status;
+    }
+    shell.last_status = status;
+    run_trap(shell, Condition::Return);
+
+    if let Some(positional) = saved_positional {
+        shell.positional = positional;
+    }
+
+    status
+}
+
+/// `return [n]`
+///
+/// Stop executing the sourced file with exit status `n`, or the exit
+/// status of the last command.
+fn return_builtin(shell: &mut Shell, argv: &[String]) -> c_int {
+    if shell.source_depth == 0 {
+        eprintln!("stsh: return: can only `return' from a sourced script");
+        return 1;
+    }
+
+    let status = match argv.get(1) {
+        Some(n) => match n.parse::<c_int>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                eprintln!("stsh: return: {}: numeric argument required", n);
+                2
+            }
+        },
+        None => shell.last_status,
+    };
+    shell.pending_return = Some(status);
+
+    status
+}
+
+/// The exit status of a conditional expression: 0 if it is true, 1 if
+/// it is false and 2 if it is invalid.
+fn condition_status(name: &str, result: Result<bool, ConditionalError>) -> c_int {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("stsh: {}: {}", name, e);
+            2
+        }
+    }
+}
+
+/// `test expression` and `[ expression ]`
+///
+/// Evaluate the conditional expression.
+fn test(shell: &mut Shell, argv: &[String]) -> c_int {
+    let mut args: Vec<&str> = argv[1..].iter().map(String::as_str).collect();
+    if argv[0] == "[" && args.pop() != Some("]") {
+        eprintln!("stsh: [: missing `]'");
+        return 2;
+    }
+
+    condition_status(&argv[0], conditional::test(shell, &args))
+}
+
+/// `[[ expression ]]`
+///
+/// Evaluate the conditional expression, given as written. It is not
+/// looked up by name, the parser recognizes it.
+pub(crate) fn conditional_command(shell: &mut Shell, argv: &[String]) -> c_int {
+    // the parser only accepts words ending with `]]`
+    let words: Vec<&str> = argv[1..argv.len() - 1].iter().map(String::as_str).collect();
+
+    condition_status("[[", conditional::conditional(shell, &words))
+}
+
+/// `shift [n]`
+///
+/// Remove the first `n` positional parameters, one by default.
+fn shift(shell: &mut Shell, argv: &[String]) -> c_int {
+    let count = match argv.get(1) {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("stsh: shift: {}: numeric argument required", n);
+                return 2;
+            }
+        },
+        None => 1,
+    };
+    if count > shell.positional.len() {
+        eprintln!("stsh: shift: {}: shift count out of range", count);
+        return 1;
+    }
+
+    shell.positional.drain(..count);
+
+    0
+}
+
+/// Print the options as `set -o` does, or, if `reinput`, as
+/// commands which restore them.
+fn print_options(shell: &Shell, reinput: bool) {
+    for (name, value) in shell.options.iter() {
+        if reinput {
+            println!("set {}o {}", if value { '-' } else { '+' }, name);
+        } else {
+            println!("{:<15} {}", name, if value { "on" } else { "off" });
+        }
+    }
+}
+
+/// `set [-+abCefnuvx] [-+o option] [--] [arg...]`
+///
+/// Without arguments, print all variables. Otherwise, set or unset
+/// options and replace the positional parameters by the remaining
+/// arguments, if any.
+fn set(shell: &mut Shell, argv: &[String]) -> c_int {
+    if argv.len() == 1 {
+        for (name, value) in shell.vars() {
+            println!("{}={}", name, quote(value));
+        }
+        return 0;
+    }
+
+    let mut args = argv[1..].iter().peekable();
+    let mut set_positional = false;
+    while let Some(arg) = args.peek() {
+        let value = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => break,
+        };
+        let arg = args.next().unwrap();
+
+        if arg == "--" {
+            set_positional = true;
+            break;
+        } else if arg == "-" {
+            // historical, turns off xtrace
+            shell.options.xtrace = false;
+            set_positional = true;
+            break;
+        }
+
+        for flag in arg[1..].chars() {
+            if flag == 'o' {
+                match args.next() {
+                    Some(name) => {
+                        if !shell.options.set(name, value) {
+                            eprintln!("stsh: set: {}: invalid option name", name);
+                            return 2;
+                        }
+                    }
+                    None => print_options(shell, !value),
+                }
+            } else if !shell.options.set_flag(flag, value) {
+                eprintln!("stsh: set: {}{}: invalid option", &arg[..1], flag);
+                return 2;
+            }
+        }
+    }
+
+    let operands: Vec<String> = args.cloned().collect();
+    if set_positional || !operands.is_empty() {
+        shell.positional = operands;
+    }
+
+    0
+}
+
+/// `exit [n]`
+///
+/// Exit the shell with status `n`, or the status of the last command.
+/// If there are stopped jobs, an interactive shell only warns the
+/// first time.
+fn exit(shell: &mut Shell, argv: &[String]) -> c_int {
+    if shell.interactive && shell.jobs.has_stopped() && !shell.exit_warned {
+        eprintln!("There are stopped jobs.");
+        shell.exit_warned = true;
+        return 1;
+    }
+
+    let status = match argv.get(1) {
+        Some(n) => match n.parse::<c_int>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                eprintln!("stsh: exit: {}: numeric argument required", n);
+                2
+            }
+        },
+        None => shell.last_status,
+    };
+
+    exit_shell(shell, status)
+}
+
+/// `disown [-h] [-a | %job...]`
+///
+/// Remove jobs from the job table, the current job if none are given,
+/// such that they are not sent `SIGHUP` when the shell exits. With
+/// `-h`, the jobs are only marked as such.
+fn disown(shell: &mut Shell, argv: &[String]) -> c_int {
+    let mut nohup = false;
+    let mut all = false;
+    let mut args = &argv[1..];
+    while let Some(option) = args
+        .first()
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        args = &args[1..];
+        if option == "--" {
+            break;
+        }
+        for flag in option[1..].chars() {
+            match flag {
+                'h' => nohup = true,
+                'a' => all = true,
+                _ => {
+                    eprintln!("stsh: disown: -{}: invalid option", flag);
+                    return 2;
+                }
+            }
+        }
+    }
+
+    let mut indices = Vec::new();
+    let mut status = 0;
+    if all {
+        indices.extend(0..shell.jobs.len());
+    } else if args.is_empty() {
+        match shell.jobs.find("%+") {
+            Some(idx) => indices.push(idx),
+            None => {
+                eprintln!("stsh: disown: current: no such job");
+                return 1;
+            }
+        }
+    }
+    for arg in args {
+        match shell.jobs.find(arg) {
+            Some(idx) => indices.push(idx),
+            None => {
+                eprintln!("stsh: disown: {}: no such job", arg);
+                status = 1;
+            }
+        }
+    }
+
+    // from the back, such that removing does not shift the others
+    indices.sort_unstable();
+    indices.dedup();
+    for idx in indices.into_iter().rev() {
+        if nohup {
+            shell.jobs.get_mut(idx).nohup = true;
+        } else {
+            shell.jobs.remove(idx);
+        }
+    }
+
+    status
+}
+
+/// Whether `name` may be used as the name of an alias.
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|chr: char| {
+            chr.is_whitespace()
+                || matches!(
+                    chr,
+                    '|' | '&' | ';' | '<' | '>' | '(' | ')' | '\'' | '"' | '\\' | '$' | '`' | '/'
+                )
+        })
+}
+
+/// `alias [-p] [name[=value]...]`
+///
+/// Define an alias for each `name=value`, print the aliases given by
+/// name or, without arguments, all of them. Aliases are printed as
+/// commands which define them again.
+fn alias(shell: &mut Shell, argv: &[String]) -> c_int {
+    let mut args = &argv[1..];
+    if args.first().is_some_and(|arg| arg == "-p" || arg == "--") {
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        for (name, value) in &shell.aliases {
+            println!("alias {}={}", name, quote(value));
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if is_alias_name(name) => {
+                shell.aliases.insert(name.to_owned(), value.to_owned());
+            }
+            Some((name, _)) => {
+                eprintln!("stsh: alias: {}: invalid alias name", name);
+                status = 1;
+            }
+            None => match shell.aliases.get(arg.as_str()) {
+                Some(value) => println!("alias {}={}", arg, quote(value)),
+                None => {
+                    eprintln!("stsh: alias: {}: not found", arg);
+                    status = 1;
+                }
+            },
+        }
+    }
+
+    status
+}
+
+/// `unalias [-a] name...`
+///
+/// Remove the named aliases, with `-a` all of them.
+fn unalias(shell: &mut Shell, argv: &[String]) -> c_int {
+    match argv.get(1).map(String::as_str) {
+        Some("-a") => {
+            shell.aliases.clear();
+            return 0;
+        }
+        None => {
+            eprintln!("stsh: unalias: usage: unalias [-a] name...");
+            return 2;
+        }
+        _ => (),
+    }
+
+    let mut status = 0;
+    for name in &argv[1..] {
+        if shell.aliases.remove(name).is_none() {
+            eprintln!("stsh: unalias: {}: not found", name);
+            status = 1;
+        }
+    }
+
+    status
+}
+
+/// Where a command is found.
+#[derive(Debug, PartialEq)]
+enum Location {
+    /// An alias with its value
+    Alias(String),
+    /// A reserved word, `[[`
+    Keyword,
+    Builtin,
+    /// Remembered by an earlier lookup in `PATH`
+    Hashed(PathBuf),
+    File(PathBuf),
+}
+
+/// Look up how command `name` would be executed, without remembering
+/// it. With `all`, return every alias, builtin and executable in `PATH`
+/// of that name, with `path_only`, only consider executables.
+fn locate(shell: &Shell, name: &str, all: bool, path_only: bool) -> Vec<Location> {
+    let mut locations = Vec::new();
+    if let Some(value) = shell.aliases.get(name).filter(|_| !path_only) {
+        locations.push(Location::Alias(value.clone()));
+        if !all {
+            return locations;
+        }
+    }
+    if !path_only && name == "[[" {
+        locations.push(Location::Keyword);
+        if !all {
+            return locations;
+        }
+    }
+    if !path_only && lookup(name).is_some() {
+        locations.push(Location::Builtin);
+        if !all {
+            return locations;
+        }
+    }
+
+    if name.contains('/') {
+        if is_executable(Path::new(name)) {
+            locations.push(Location::File(PathBuf::from(name)));
+        }
+    } else if let Some(hashed) = shell.hash.get(name).filter(|_| !all && !path_only) {
+        locations.push(Location::Hashed(hashed.path.clone()));
+    } else {
+        let paths = search_path(shell, name);
+        let count = if all { paths.len() } else { 1 };
+        locations.extend(paths.into_iter().take(count).map(Location::File));
+    }
+
+    locations
+}
+
+fn describe_location(name: &str, location: &Location) -> String {
+    match location {
+        Location::Alias(value) => format!("{} is aliased to `{}'", name, value),
+        Location::Keyword => format!("{} is a shell keyword", name),
+        Location::Builtin => format!("{} is a shell builtin", name),
+        Location::Hashed(path) => format!("{} is hashed ({})", name, path.display()),
+        Location::File(path) => format!("{} is {}", name, path.display()),
+    }
+}
+
+/// `command [-v|-V] name...`
+///
+/// Describe commands, with `-v` briefly by the path or name which is
+/// executed, with `-V` verbosely. Running a command, i.e. `command`
+/// without options, is handled before builtins are looked up.
+fn command(shell: &mut Shell, argv: &[String]) -> c_int {
+    let (verbose, names) = match argv.get(1).map(String::as_str) {
+        None => return 0,
+        Some("-v") => (false, &argv[2..]),
+        Some("-V") => (true, &argv[2..]),
+        Some(option) => {
+            eprintln!("stsh: command: {}: invalid option", option);
+            return 2;
+        }
+    };
+
+    let mut status = 0;
+    for name in names {
+        match locate(shell, name, false, false).first() {
+            Some(location) if verbose => println!("{}", describe_location(name, location)),
+            Some(Location::Alias(value)) => println!("alias {}={}", name, quote(value)),
+            Some(Location::Keyword) | Some(Location::Builtin) => println!("{}", name),
+            Some(Location::Hashed(path)) | Some(Location::File(path)) => {
+                println!("{}", path.display())
+            }
+            None => {
+                if verbose {
+                    eprintln!("stsh: command: {}: not found", name);
+                }
+                status = 1;
+            }
+        }
+    }
+
+    status
+}
+
+/// `type [-a] [-t|-p|-P] name...`
+///
+/// Tell how each command would be executed. With `-t`, only print
+/// `alias`, `builtin` or `file`, with `-p`, only print the path of
+/// executables, with `-P`, search `PATH` even for builtins. With `-a`,
+/// show all places a command is found at, like `which -a`.
+fn type_builtin(shell: &mut Shell, argv: &[String]) -> c_int {
+    let (mut all, mut kind_only, mut path_only, mut force_path) = (false, false, false, false);
+    let mut names = &argv[1..];
+    while let Some(option) = names
+        .first()
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        names = &names[1..];
+        if option == "--" {
+            break;
+        }
+        for flag in option[1..].chars() {
+            match flag {
+                'a' => all = true,
+                't' => kind_only = true,
+                'p' => path_only = true,
+                'P' => force_path = true,
+                _ => {
+                    eprintln!("stsh: type: -{}: invalid option", flag);
+                    return 2;
+                }
+            }
+        }
+    }
+
+    let mut status = 0;
+    for name in names {
+        let locations = locate(shell, name, all, force_path);
+        if locations.is_empty() {
+            if !kind_only && !path_only && !force_path {
+                eprintln!("stsh: type: {}: not found", name);
+            }
+            status = 1;
+        }
+
+        for location in &locations {
+            match location {
+                _ if kind_only => match location {
+                    Location::Alias(_) => println!("alias"),
+                    Location::Keyword => println!("keyword"),
+                    Location::Builtin => println!("builtin"),
+                    _ => println!("file"),
+                },
+                Location::Alias(_) | Location::Keyword | Location::Builtin
+                    if path_only || force_path => {}
+                Location::Hashed(path) | Location::File(path) if path_only || force_path => {
+                    println!("{}", path.display())
+                }
+                _ => println!("{}", describe_location(name, location)),
+            }
+        }
+    }
+
+    status
+}
+
+/// `which [-a] name...`
+///
+/// Print the path of the executable each name is found at in `PATH`,
+/// with `-a` of every one. Aliases and builtins are not considered. The
+/// status is 1 if a name is not found.
+fn which(shell: &mut Shell, argv: &[String]) -> c_int {
+    let (all, names) = match argv.get(1).map(String::as_str) {
+        Some("-a") => (true, &argv[2..]),
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            eprintln!("stsh: which: {}: invalid option", option);
+            return 2;
+        }
+        _ => (false, &argv[1..]),
+    };
+
+    let mut status = 0;
+    for name in names {
+        let locations = locate(shell, name, all, true);
+        if locations.is_empty() {
+            status = 1;
+        }
+        for location in locations {
+            if let Location::Hashed(path) | Location::File(path) = location {
+                println!("{}", path.display());
+            }
+        }
+    }
+
+    status
+}
+
+/// `hash [-r] [-d] [-t] [-p path] [name...]`
+///
+/// Without arguments, list the remembered commands with the number of
+/// times they were used. Otherwise look up and remember the named
+/// commands: `-p` remembers `path` for the name, `-d` forgets the
+/// names, `-t` prints their paths and `-r` forgets all commands.
+fn hash(shell: &mut Shell, argv: &[String]) -> c_int {
+    let (mut forget, mut print) = (false, false);
+    let mut path = None;
+    let mut args = &argv[1..];
+    while let Some(option) = args
+        .first()
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        args = &args[1..];
+        match option.as_str() {
+            "--" => break,
+            "-r" => shell.hash.clear(),
+            "-d" => forget = true,
+            "-t" => print = true,
+            "-p" => match args.split_first() {
+                Some((arg, rest)) => {
+                    path = Some(PathBuf::from(arg));
+                    args = rest;
+                }
+                None => {
+                    eprintln!("stsh: hash: -p: option requires an argument");
+                    return 2;
+                }
+            },
+            _ => {
+                eprintln!("stsh: hash: {}: invalid option", option);
+                return 2;
+            }
+        }
+    }
+
+    if args.is_empty() {
+        if argv.len() == 1 {
+            if shell.hash.is_empty() {
+                println!("hash: hash table empty");
+            } else {
+                println!("hits\tcommand");
+                for (_, hashed) in shell.hash.iter() {
+                    println!("{:4}\t{}", hashed.hits, hashed.path.display());
+                }
+            }
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for name in args {
+        if forget {
+            if !shell.hash.remove(name) {
+                eprintln!("stsh: hash: {}: not found", name);
+                status = 1;
+            }
+        } else if print {
+            match shell.hash.get(name) {
+                Some(hashed) if args.len() > 1 => {
+                    println!("{}\t{}", name, hashed.path.display())
+                }
+                Some(hashed) => println!("{}", hashed.path.display()),
+                None => {
+                    eprintln!("stsh: hash: {}: not found", name);
+                    status = 1;
+                }
+            }
+        } else if let Some(ref path) = path {
+            shell.hash.insert(name, path.clone());
+        } else if !name.contains('/') && lookup(name).is_none() {
+            match search_path(shell, name).into_iter().next() {
+                Some(path) => shell.hash.insert(name, path),
+                None => {
+                    eprintln!("stsh: hash: {}: not found", name);
+                    status = 1;
+                }
+            }
+        }
+    }
+
+    status
+}
+
+/// Look up a signal for `kill`, which also accepts 0.
+fn kill_signal(name: &str) -> Option<c_int> {
+    if name == "0" {
+        Some(0)
+    } else {
+        signal_number(name)
+    }
+}
+
+/// `kill -l [signal|status...]`
+///
+/// List the signals, or convert signal numbers and exit statuses of
+/// killed processes to names and names to numbers.
+fn list_signals(args: &[String]) -> c_int {
+    if args.is_empty() {
+        for (signum, name) in SIGNALS {
+            println!("{:2}) SIG{}", signum, name);
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        match arg.parse::<c_int>() {
+            Ok(number) => {
+                let signum = if number > 128 { number - 128 } else { number };
+                match signal_name(signum) {
+                    Some(name) => println!("{}", name),
+                    None => {
+                        eprintln!("stsh: kill: {}: invalid signal specification", arg);
+                        status = 1;
+                    }
+                }
+            }
+            Err(_) => match signal_number(arg) {
+                Some(signum) => println!("{}", signum),
+                None => {
+                    eprintln!("stsh: kill: {}: invalid signal specification", arg);
+                    status = 1;
+                }
+            },
+        }
+    }
+
+    status
+}
+
+/// `kill [-s signal | -signal] pid|%job...`, `kill -l [signal...]`
+///
+/// Send a signal, `TERM` by default, to processes. Jobs are signalled
+/// as a whole process group.
+fn kill(shell: &mut Shell, argv: &[String]) -> c_int {
+    let mut args = &argv[1..];
+    let mut signum = libc::SIGTERM;
+
+    match args.first().map(String::as_str) {
+        Some("-l") | Some("-L") => return list_signals(&args[1..]),
+        Some("-s") | Some("-n") => {
+            let name = match args.get(1) {
+                Some(name) => name,
+                None => {
+                    eprintln!("stsh: kill: {}: option requires an argument", args[0]);
+                    return 2;
+                }
+            };
+            signum = match kill_signal(name) {
+                Some(signum) => signum,
+                None => {
+                    eprintln!("stsh: kill: {}: invalid signal specification", name);
+                    return 1;
+                }
+            };
+            args = &args[2..];
+        }
+        Some("--") => args = &args[1..],
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            signum = match kill_signal(&option[1..]) {
+                Some(signum) => signum,
+                None => {
+                    eprintln!("stsh: kill: {}: invalid signal specification", &option[1..]);
+                    return 1;
+                }
+            };
+            args = &args[1..];
+        }
+        _ => (),
+    }
+    if args.first().is_some_and(|arg| arg == "--") {
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        eprintln!(
+            "stsh: kill: usage: kill [-s signal | -signal] pid | %job ... or kill -l [signal]"
+        );
+        return 2;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let pid = if arg.starts_with('%') {
+            match shell.jobs.find(arg) {
+                Some(idx) => -shell.jobs.get(idx).pgid,
+                None => {
+                    eprintln!("stsh: kill: {}: no such job", arg);
+                    status = 1;
+                    continue;
+                }
+            }
+        } else {
+            match arg.parse::<pid_t>() {
+                Ok(pid) => pid,
+                Err(_) => {
+                    eprintln!("stsh: kill: {}: arguments must be process or job IDs", arg);
+                    status = 1;
+                    continue;
+                }
+            }
+        };
+
+        if unsafe { libc::kill(pid, signum) } == -1 {
+            let error = unsafe { CStr::from_ptr(strerror(*__errno_location())) };
+            eprintln!("stsh: kill: ({}) - {}", pid, error.to_string_lossy());
+            status = 1;
+        }
+    }
+
+    status
+}
+
+fn print_trap(condition: Condition, action: &str) {
+    println!("trap -- {} {}", quote(action), condition.name());
+}
+
+/// `trap [action condition...]`, `trap -p [condition...]`, `trap -l`
+///
+/// Set `action` to be executed on each condition, which is either a
+/// signal or one of `EXIT`, `ERR`, `DEBUG` and `RETURN`. An empty
+/// action ignores the conditions, `-` resets them to the default, as
+/// does omitting the action or giving a signal number first. Without
+/// arguments or with `-p`, print the traps.
+fn trap(shell: &mut Shell, argv: &[String]) -> c_int {
+    let mut args = &argv[1..];
+    match args.first().map(String::as_str) {
+        Some("-l") => {
+            for (signum, name) in SIGNALS {
+                println!("{:2}) SIG{}", signum, name);
+            }
+            return 0;
+        }
+        Some("-p") if args.len() > 1 => {
+            let mut status = 0;
+            for name in &args[1..] {
+                match Condition::parse(name) {
+                    Some(condition) => {
+                        if let Some(action) = shell.traps.get(&condition) {
+                            print_trap(condition, action);
+                        }
+                    }
+                    None => {
+                        eprintln!("stsh: trap: {}: invalid signal specification", name);
+                        status = 1;
+                    }
+                }
+            }
+            return status;
+        }
+        None | Some("-p") => {
+            for (condition, action) in &shell.traps {
+                print_trap(*condition, action);
+            }
+            return 0;
+        }
+        Some("--") => args = &args[1..],
+        _ => (),
+    }
+
+    let is_number = |arg: &str| !arg.is_empty() && arg.chars().all(|chr| chr.is_ascii_digit());
+    let (action, conditions) = match args.split_first() {
+        None => return 0,
+        Some((first, _)) if args.len() == 1 || is_number(first) => (None, args),
+        Some((first, rest)) if first == "-" => (None, rest),
+        Some((first, rest)) => (Some(first.as_str()), rest),
+    };
+
+    let mut status = 0;
+    for name in conditions {
+        let condition = match Condition::parse(name) {
+            Some(condition) => condition,
+            None => {
+                eprintln!("stsh: trap: {}: invalid signal specification", name);
+                status = 1;
+                continue;
+            }
+        };
+        if let Err(e) = set_trap(shell, condition, action) {
+            eprintln!("stsh: trap: {}: {}", name, e);
+            status = 1;
+        }
+    }
+
+    status
+}
+
+/// `wait [-n] [pid|%job...]`
+///
+/// Wait for the given processes or jobs and return the exit status of
+/// the last one, or for all jobs if none are given. With `-n`, wait
+/// for any single job. The status of a process whose job was reported
+/// as finished already can still be waited for once. A signal a trap
+/// is set for ends waiting with 128 plus its number, after executing
+/// the trap.
+fn wait(shell: &mut Shell, argv: &[String]) -> c_int {
+    // the traps of signals caught before ran already
+    take_trapped_signal();
+    match wait_for(shell, &argv[1..]) {
+        Ok(status) => status,
+        Err(signum) => {
+            handle_pending_signals(shell);
+            128 + signum
+        }
+    }
+}
+
+/// Wait for the jobs and processes `args` refer to like `wait` does,
+/// returning the number of a trapped signal which interrupted it as
+/// the error.
+fn wait_for(shell: &mut Shell, args: &[String]) -> Result<c_int, c_int> {
+    if args.first().is_some_and(|arg| arg == "-n") {
+        return match shell.jobs.wait_any()? {
+            Some(job) => Ok(job.status()),
+            None => Ok(127),
+        };
+    }
+
+    if args.is_empty() {
+        shell.jobs.wait_all()?;
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        status = if arg.starts_with('%') {
+            match shell.jobs.find(arg) {
+                Some(idx) => shell.jobs.wait(idx)?.status(),
+                None => {
+                    eprintln!("stsh: wait: {}: no such job", arg);
+                    127
+                }
+            }
+        } else {
+            match arg.parse::<pid_t>() {
+                Ok(pid) => match shell.jobs.find_pid(pid) {
+                    Some(idx) => {
+                        let job = shell.jobs.wait(idx)?;
+                        job.process_status(pid).unwrap_or_else(|| job.status())
+                    }
+                    None => match shell.jobs.take_reaped(pid) {
+                        Some(status) => status,
+                        None => {
+                            eprintln!("stsh: wait: pid {} is not a child of this shell", pid);
+                            127
+                        }
+                    },
+                },
+                Err(_) => {
+                    eprintln!("stsh: wait: `{}': not a pid or valid job spec", arg);
+                    2
+                }
+            }
+        };
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::shell::Shell;
+    use crate::testutil::TempDir;
+    use crate::trap::Condition;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_source_return() {
+        let dir = TempDir::new("source");
+        let lib = dir.join("lib.sh");
+        fs::write(&lib, "return 3\nreturn 4\n").unwrap();
+
+        let mut shell = Shell::default();
+        shell.positional = argv(&["x"]);
+        shell.set_var("PATH", &dir.to_string_lossy());
+
+        assert_eq!(
+            super::source(&mut shell, &argv(&[".", "lib.sh", "a", "b"])),
+            3
+        );
+        assert_eq!(shell.positional, argv(&["x"]));
+        assert_eq!(shell.pending_return, None);
+        assert_eq!(shell.source_depth, 0);
+
+        assert_eq!(super::source(&mut shell, &argv(&["source"])), 2);
+        assert_eq!(super::return_builtin(&mut shell, &argv(&["return"])), 1);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut shell = Shell::default();
+
+        assert_eq!(
+            super::set(&mut shell, &argv(&["set", "-eu", "-o", "pipefail"])),
+            0
+        );
+        assert_eq!(shell.flags(), "eu");
+        assert!(shell.options.pipefail);
+
+        assert_eq!(super::set(&mut shell, &argv(&["set", "+e", "a", "b"])), 0);
+        assert_eq!(shell.flags(), "u");
+        assert_eq!(shell.positional, argv(&["a", "b"]));
+
+        assert_eq!(super::set(&mut shell, &argv(&["set", "-x"])), 0);
+        assert_eq!(shell.positional, argv(&["a", "b"]));
+
+        assert_eq!(super::set(&mut shell, &argv(&["set", "--"])), 0);
+        assert!(shell.positional.is_empty());
+
+        assert_eq!(super::set(&mut shell, &argv(&["set", "-q"])), 2);
+        assert_eq!(super::set(&mut shell, &argv(&["set", "-o", "nosuch"])), 2);
+        assert_eq!(shell.flags(), "ux");
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut shell = Shell::default();
+        shell.positional = argv(&["a", "b", "c"]);
+
+        assert_eq!(super::shift(&mut shell, &argv(&["shift"])), 0);
+        assert_eq!(shell.positional, argv(&["b", "c"]));
+        assert_eq!(super::shift(&mut shell, &argv(&["shift", "3"])), 1);
+        assert_eq!(super::shift(&mut shell, &argv(&["shift", "x"])), 2);
+        assert_eq!(shell.positional, argv(&["b", "c"]));
+        assert_eq!(super::shift(&mut shell, &argv(&["shift", "2"])), 0);
+        assert!(shell.positional.is_empty());
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut shell = Shell::default();
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                libc::setpgid(0, 0);
+                libc::pause();
+                libc::_exit(0);
+            }
+        }
+        unsafe { libc::setpgid(pid, pid) };
+        shell.jobs.add(pid, &[pid], "pause".to_owned());
+
+        assert_eq!(super::kill(&mut shell, &argv(&["kill", "-0", "%1"])), 0);
+        assert_eq!(super::kill(&mut shell, &argv(&["kill", "-FOO", "%1"])), 1);
+        assert_eq!(super::kill(&mut shell, &argv(&["kill", "%2"])), 1);
+        assert_eq!(super::kill(&mut shell, &argv(&["kill", "-s"])), 2);
+        assert_eq!(super::kill(&mut shell, &argv(&["kill"])), 2);
+        assert_eq!(
+            super::kill(&mut shell, &argv(&["kill", "-s", "KILL", "%pause"])),
+            0
+        );
+        assert_eq!(shell.jobs.wait(0).unwrap().status(), 128 + libc::SIGKILL);
+
+        assert_eq!(super::list_signals(&argv(&["9", "137", "HUP"])), 0);
+        assert_eq!(super::list_signals(&argv(&["FOO"])), 1);
+    }
+
+    #[test]
+    fn test_wait_trapped_signal() {
+        let mut shell = Shell::default();
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", ":", "ALRM"])), 0);
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                libc::pause();
+                libc::_exit(0);
+            }
+        }
+        shell.jobs.add(pid, &[pid], "pause".to_owned());
+
+        // sent to this very thread, which waits meanwhile
+        let waiting = unsafe { libc::pthread_self() };
+        let alarm = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            unsafe { libc::pthread_kill(waiting, libc::SIGALRM) };
+        });
+        assert_eq!(
+            super::wait(&mut shell, &argv(&["wait", "%1"])),
+            128 + libc::SIGALRM
+        );
+        alarm.join().unwrap();
+        assert_eq!(shell.jobs.find_pid(pid), Some(0));
+
+        unsafe { libc::kill(pid, libc::SIGKILL) };
+        assert_eq!(
+            super::wait(&mut shell, &argv(&["wait", &pid.to_string()])),
+            128 + libc::SIGKILL
+        );
+    }
+
+    #[test]
+    fn test_disown() {
+        let mut shell = Shell::default();
+        for (pid, command) in [(1, "a"), (2, "b"), (3, "c")] {
+            shell.jobs.add(pid, &[pid], command.to_owned());
+        }
+
+        assert_eq!(super::disown(&mut shell, &argv(&["disown", "-h", "%1"])), 0);
+        assert!(shell.jobs.get(0).nohup);
+        assert_eq!(super::disown(&mut shell, &argv(&["disown"])), 0);
+        assert_eq!(shell.jobs.find("%c"), None);
+        assert_eq!(super::disown(&mut shell, &argv(&["disown", "%5"])), 1);
+        assert_eq!(super::disown(&mut shell, &argv(&["disown", "-x"])), 2);
+        assert_eq!(super::disown(&mut shell, &argv(&["disown", "-a"])), 0);
+        assert_eq!(shell.jobs.len(), 0);
+        assert_eq!(super::disown(&mut shell, &argv(&["disown"])), 1);
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut shell = Shell::default();
+
+        assert_eq!(
+            super::alias(&mut shell, &argv(&["alias", "ll=ls -l", "la=ls -a"])),
+            0
+        );
+        assert_eq!(shell.aliases.get("ll").map(String::as_str), Some("ls -l"));
+        assert_eq!(super::alias(&mut shell, &argv(&["alias", "ll"])), 0);
+        assert_eq!(super::alias(&mut shell, &argv(&["alias", "nosuch"])), 1);
+        assert_eq!(super::alias(&mut shell, &argv(&["alias", "a/b=x"])), 1);
+        assert_eq!(
+            super::locate(&shell, "ll", false, false),
+            vec![super::Location::Alias("ls -l".to_owned())]
+        );
+
+        assert_eq!(super::unalias(&mut shell, &argv(&["unalias", "ll"])), 0);
+        assert_eq!(super::unalias(&mut shell, &argv(&["unalias", "ll"])), 1);
+        assert_eq!(super::unalias(&mut shell, &argv(&["unalias", "-a"])), 0);
+        assert!(shell.aliases.is_empty());
+    }
+
+    #[test]
+    fn test_locate() {
+        let mut shell = Shell::default();
+        shell.set_var("PATH", "/usr/bin:/bin");
+
+        assert_eq!(
+            super::locate(&shell, "kill", false, false),
+            vec![super::Location::Builtin]
+        );
+        assert_eq!(
+            super::locate(&shell, "[[", false, false),
+            vec![super::Location::Keyword]
+        );
+        assert_eq!(
+            super::locate(&shell, "sh", false, false),
+            vec![super::Location::File(
+                super::search_path(&shell, "sh").remove(0)
+            )]
+        );
+        assert!(super::locate(&shell, "no-such-command", true, false).is_empty());
+
+        assert_eq!(super::hash(&mut shell, &argv(&["hash", "sh"])), 0);
+        assert!(matches!(
+            super::locate(&shell, "sh", false, false)[0],
+            super::Location::Hashed(_)
+        ));
+        assert_eq!(super::hash(&mut shell, &argv(&["hash", "-d", "sh"])), 0);
+        assert_eq!(super::hash(&mut shell, &argv(&["hash", "-d", "sh"])), 1);
+        assert_eq!(
+            super::hash(&mut shell, &argv(&["hash", "no-such-command"])),
+            1
+        );
+
+        assert_eq!(
+            super::command(&mut shell, &argv(&["command", "-v", "sh"])),
+            0
+        );
+        assert_eq!(
+            super::command(&mut shell, &argv(&["command", "-V", "no-such-command"])),
+            1
+        );
+        assert_eq!(
+            super::type_builtin(&mut shell, &argv(&["type", "-t", "kill", "sh"])),
+            0
+        );
+
+        assert_eq!(super::which(&mut shell, &argv(&["which", "-a", "sh"])), 0);
+        assert_eq!(
+            super::which(&mut shell, &argv(&["which", "sh", "no-such-command"])),
+            1
+        );
+        assert_eq!(super::which(&mut shell, &argv(&["which", "-x"])), 2);
+    }
+
+    #[test]
+    fn test_trap() {
+        let mut shell = Shell::default();
+
+        assert_eq!(
+            super::trap(&mut shell, &argv(&["trap", "echo bye", "EXIT", "ERR"])),
+            0
+        );
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", "", "USR2"])), 0);
+        assert_eq!(shell.traps.len(), 3);
+        assert_eq!(
+            shell.traps.get(&Condition::Signal(libc::SIGUSR2)),
+            Some(&String::new())
+        );
+
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", "-", "ERR"])), 0);
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", "USR2"])), 0);
+        assert_eq!(
+            shell.traps.keys().collect::<Vec<_>>(),
+            vec![&Condition::Exit]
+        );
+
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", "0"])), 0);
+        assert!(shell.traps.is_empty());
+
+        assert_eq!(super::trap(&mut shell, &argv(&["trap", "x", "NOSUCH"])), 1);
+    }
+}
@@ -0,0 +1,631 @@
+//! Conditional expressions of `test`, `[` and `[[ ]]`
+//!
+//! https://pubs.opengroup.org/onlinepubs/9699919799/utilities/test.html
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, Metadata};
+use std::mem::MaybeUninit;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use libc::{
+    access, c_int, fnmatch, isatty, regcomp, regex_t, regexec, regfree, regmatch_t, REG_EXTENDED,
+    R_OK, S_ISGID, S_ISUID, W_OK, X_OK,
+};
+
+use crate::expansion::{expand_pattern, expand_regex, expand_string, ExpansionError};
+use crate::shell::Shell;
+
+#[derive(Debug, PartialEq)]
+pub enum ConditionalError {
+    /// An operand or an operator is missing or out of place
+    Syntax(String),
+    Integer(String),
+    Regex(String),
+    Expansion(ExpansionError),
+}
+
+impl Display for ConditionalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionalError::Syntax(message) => write!(f, "{}", message),
+            ConditionalError::Integer(operand) => {
+                write!(f, "{}: integer expression expected", operand)
+            }
+            ConditionalError::Regex(regex) => {
+                write!(f, "{}: invalid regular expression", regex)
+            }
+            ConditionalError::Expansion(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConditionalError {}
+
+impl From<ExpansionError> for ConditionalError {
+    fn from(e: ExpansionError) -> Self {
+        ConditionalError::Expansion(e)
+    }
+}
+
+/// The arguments of `test` are operands as they are, the words of
+/// `[[ ]]` are expanded when their value is needed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Syntax {
+    Test,
+    Conditional,
+}
+
+impl Syntax {
+    fn and_operator(self) -> &'static str {
+        match self {
+            Syntax::Test => "-a",
+            Syntax::Conditional => "&&",
+        }
+    }
+
+    fn or_operator(self) -> &'static str {
+        match self {
+            Syntax::Test => "-o",
+            Syntax::Conditional => "||",
+        }
+    }
+
+    fn is_binary(self, op: &str) -> bool {
+        matches!(
+            op,
+            "=" | "=="
+                | "!="
+                | "<"
+                | ">"
+                | "-eq"
+                | "-ne"
+                | "-lt"
+                | "-le"
+                | "-gt"
+                | "-ge"
+                | "-nt"
+                | "-ot"
+                | "-ef"
+        ) || (self == Syntax::Conditional && op == "=~")
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    matches!(
+        op,
+        "-b" | "-c"
+            | "-d"
+            | "-e"
+            | "-f"
+            | "-g"
+            | "-h"
+            | "-L"
+            | "-n"
+            | "-p"
+            | "-r"
+            | "-S"
+            | "-s"
+            | "-t"
+            | "-u"
+            | "-w"
+            | "-x"
+            | "-z"
+    )
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr<'a> {
+    /// True if the operand is not empty
+    String(&'a str),
+    Unary(&'a str, &'a str),
+    Binary(&'a str, &'a str, &'a str),
+    Not(Box<Expr<'a>>),
+    And(Box<Expr<'a>>, Box<Expr<'a>>),
+    Or(Box<Expr<'a>>, Box<Expr<'a>>),
+}
+
+/// Recursive descent parser of expressions, `-o` or `||` binding less
+/// tightly than `-a` or `&&`, which bind less tightly than `!`.
+struct Parser<'a, 'b> {
+    words: &'b [&'a str],
+    pos: usize,
+    syntax: Syntax,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.peek();
+        self.pos += 1;
+        word
+    }
+
+    fn parse(mut self) -> Result<Expr<'a>, ConditionalError> {
+        let expr = self.or()?;
+        match self.peek() {
+            Some(word) => Err(ConditionalError::Syntax(format!(
+                "{}: unexpected argument",
+                word
+            ))),
+            None => Ok(expr),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr<'a>, ConditionalError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(self.syntax.or_operator()) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr<'a>, ConditionalError> {
+        let mut expr = self.not()?;
+        while self.peek() == Some(self.syntax.and_operator()) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr<'a>, ConditionalError> {
+        // a final `!` is an operand
+        if self.peek() == Some("!") && self.pos + 1 < self.words.len() {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        self.primary()
+    }
+
+    /// Parse a binary or unary expression, an expression in parentheses
+    /// or a single operand, in this order of precedence.
+    fn primary(&mut self) -> Result<Expr<'a>, ConditionalError> {
+        let word = self
+            .next()
+            .ok_or_else(|| ConditionalError::Syntax("argument expected".to_owned()))?;
+        let remaining = self.words.len().saturating_sub(self.pos);
+
+        if let Some(op) = self.peek() {
+            if remaining >= 2 && self.syntax.is_binary(op) {
+                self.pos += 1;
+                let right = self.next().unwrap();
+                return Ok(Expr::Binary(word, op, right));
+            }
+        }
+        if word == "(" && remaining > 0 {
+            let expr = self.or()?;
+            return match self.next() {
+                Some(")") => Ok(expr),
+                _ => Err(ConditionalError::Syntax("`)' expected".to_owned())),
+            };
+        }
+        if remaining > 0 && is_unary(word) {
+            let operand = self.next().unwrap();
+            return Ok(Expr::Unary(word, operand));
+        }
+
+        Ok(Expr::String(word))
+    }
+}
+
+/// Parse the arguments of `test`. Up to four arguments are parsed by the
+/// rules of POSIX, which tell operators and operands like `!` or `(`
+/// apart by the number of arguments.
+fn parse_test<'a>(args: &[&'a str]) -> Result<Expr<'a>, ConditionalError> {
+    let string = |arg| Box::new(Expr::String(arg));
+    match *args {
+        [arg] => Ok(Expr::String(arg)),
+        ["!", arg] => Ok(Expr::Not(string(arg))),
+        [op, arg] if is_unary(op) => Ok(Expr::Unary(op, arg)),
+        [left, "-a", right] => Ok(Expr::And(string(left), string(right))),
+        [left, "-o", right] => Ok(Expr::Or(string(left), string(right))),
+        [left, op, right] if Syntax::Test.is_binary(op) => Ok(Expr::Binary(left, op, right)),
+        ["!", ..] if args.len() <= 4 => Ok(Expr::Not(Box::new(parse_test(&args[1..])?))),
+        ["(", ref inner @ .., ")"] if args.len() <= 4 => parse_test(inner),
+        _ => Parser {
+            words: args,
+            pos: 0,
+            syntax: Syntax::Test,
+        }
+        .parse(),
+    }
+}
+
+/// The value of `word`, which is expanded for `[[ ]]`.
+fn operand(shell: &Shell, word: &str, syntax: Syntax) -> Result<String, ConditionalError> {
+    match syntax {
+        Syntax::Test => Ok(word.to_owned()),
+        Syntax::Conditional => Ok(expand_string(shell, word)?),
+    }
+}
+
+impl Expr<'_> {
+    /// Evaluate the expression. `-a`, `&&`, `-o` and `||` only evaluate
+    /// their right operand if needed.
+    fn evaluate(&self, shell: &mut Shell, syntax: Syntax) -> Result<bool, ConditionalError> {
+        match self {
+            Expr::String(word) => Ok(!operand(shell, word, syntax)?.is_empty()),
+            Expr::Unary(op, word) => Ok(unary(op, &operand(shell, word, syntax)?)),
+            Expr::Binary(left, op, right)
+                if syntax == Syntax::Conditional && matches!(*op, "=" | "==" | "!=") =>
+            {
+                let string = expand_string(shell, left)?;
+                let pattern = expand_pattern(shell, right)?;
+                Ok(matches_pattern(&pattern, &string) == (*op != "!="))
+            }
+            Expr::Binary(left, op, right) if *op == "=~" => {
+                let string = expand_string(shell, left)?;
+                let regex = expand_regex(shell, right)?;
+                let matches = regex_match(&regex, &string)?;
+                let matched = matches.is_some();
+                shell.rematch = matches.unwrap_or_default();
+                Ok(matched)
+            }
+            Expr::Binary(left, op, right) => binary(
+                &operand(shell, left, syntax)?,
+                op,
+                &operand(shell, right, syntax)?,
+            ),
+            Expr::Not(expr) => Ok(!expr.evaluate(shell, syntax)?),
+            Expr::And(left, right) => {
+                Ok(left.evaluate(shell, syntax)? && right.evaluate(shell, syntax)?)
+            }
+            Expr::Or(left, right) => {
+                Ok(left.evaluate(shell, syntax)? || right.evaluate(shell, syntax)?)
+            }
+        }
+    }
+}
+
+fn is_accessible(path: &str, mode: c_int) -> bool {
+    let c_path = match CString::new(path) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+
+    unsafe { access(c_path.as_ptr(), mode) == 0 }
+}
+
+fn file_test(op: &str, metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    match op {
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-d" => file_type.is_dir(),
+        "-e" => true,
+        "-f" => file_type.is_file(),
+        "-g" => metadata.mode() & S_ISGID != 0,
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-s" => metadata.len() > 0,
+        "-u" => metadata.mode() & S_ISUID != 0,
+        _ => false,
+    }
+}
+
+/// Apply the unary operator `op`, a string test or a test of the file
+/// `operand`, or of the file descriptor for `-t`.
+fn unary(op: &str, operand: &str) -> bool {
+    match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-t" => operand
+            .parse::<c_int>()
+            .is_ok_and(|fd| unsafe { isatty(fd) } == 1),
+        "-h" | "-L" => {
+            fs::symlink_metadata(operand).is_ok_and(|metadata| metadata.file_type().is_symlink())
+        }
+        "-r" => is_accessible(operand, R_OK),
+        "-w" => is_accessible(operand, W_OK),
+        "-x" => is_accessible(operand, X_OK),
+        _ => fs::metadata(operand).is_ok_and(|metadata| file_test(op, &metadata)),
+    }
+}
+
+/// Apply the binary operator `op`, a string comparison, an integer
+/// comparison or a comparison of files.
+fn binary(left: &str, op: &str, right: &str) -> Result<bool, ConditionalError> {
+    let integer = |operand: &str| {
+        operand
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| ConditionalError::Integer(operand.to_owned()))
+    };
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified());
+
+    let result = match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        // a missing file is older than any other
+        "-nt" => match (modified(left), modified(right)) {
+            (Ok(left), Ok(right)) => left > right,
+            (left, _) => left.is_ok(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Ok(left), Ok(right)) => left < right,
+            (_, right) => right.is_ok(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        _ => {
+            return Err(ConditionalError::Syntax(format!(
+                "{}: binary operator expected",
+                op
+            )))
+        }
+    };
+
+    Ok(result)
+}
+
+fn matches_pattern(pattern: &str, string: &str) -> bool {
+    let (pattern, string) = match (CString::new(pattern), CString::new(string)) {
+        (Ok(pattern), Ok(string)) => (pattern, string),
+        _ => return false,
+    };
+
+    unsafe { fnmatch(pattern.as_ptr(), string.as_ptr(), 0) == 0 }
+}
+
+/// Count the subexpressions of `regex`, i.e. the opening parentheses
+/// which are neither escaped nor within a bracket expression.
+fn subexpressions(regex: &str) -> usize {
+    let mut count = 0;
+    let mut chars = regex.chars().peekable();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                // a `]` right at the start belongs to the expression
+                chars.next_if(|&c| c == '^');
+                chars.next_if(|&c| c == ']');
+                while chars.next().is_some_and(|c| c != ']') {}
+            }
+            '(' => count += 1,
+            _ => (),
+        }
+    }
+
+    count
+}
+
+/// Match `string` against the extended regular expression `regex`.
+/// Return the matched text followed by the text matched by each
+/// subexpression, or `None` if there is no match.
+fn regex_match(regex: &str, string: &str) -> Result<Option<Vec<String>>, ConditionalError> {
+    let invalid = || ConditionalError::Regex(regex.to_owned());
+    let c_regex = CString::new(regex).map_err(|_| invalid())?;
+    let c_string = match CString::new(string) {
+        Ok(c_string) => c_string,
+        Err(_) => return Ok(None),
+    };
+
+    let mut compiled = MaybeUninit::<regex_t>::uninit();
+    if unsafe { regcomp(compiled.as_mut_ptr(), c_regex.as_ptr(), REG_EXTENDED) } != 0 {
+        return Err(invalid());
+    }
+    let mut compiled = unsafe { compiled.assume_init() };
+    let mut matches = vec![
+        regmatch_t {
+            rm_so: -1,
+            rm_eo: -1
+        };
+        subexpressions(regex) + 1
+    ];
+    let result = unsafe {
+        regexec(
+            &compiled,
+            c_string.as_ptr(),
+            matches.len(),
+            matches.as_mut_ptr(),
+            0,
+        )
+    };
+    unsafe { regfree(&mut compiled) };
+    if result != 0 {
+        return Ok(None);
+    }
+
+    // unmatched subexpressions have negative offsets
+    let text = |m: &regmatch_t| match (usize::try_from(m.rm_so), usize::try_from(m.rm_eo)) {
+        (Ok(start), Ok(end)) => {
+            String::from_utf8_lossy(&string.as_bytes()[start..end]).into_owned()
+        }
+        _ => String::new(),
+    };
+
+    Ok(Some(matches.iter().map(text).collect()))
+}
+
+/// Evaluate the arguments of `test`, without the closing `]` of `[`.
+/// No arguments are false.
+pub(crate) fn test(shell: &mut Shell, args: &[&str]) -> Result<bool, ConditionalError> {
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    parse_test(args)?.evaluate(shell, Syntax::Test)
+}
+
+/// Evaluate the words between `[[` and `]]` as written. They are
+/// expanded without field splitting and pathname expansion, `==` and
+/// `!=` match the right operand as a pattern and `=~` as an extended
+/// regular expression, setting `BASH_REMATCH`.
+pub(crate) fn conditional(shell: &mut Shell, words: &[&str]) -> Result<bool, ConditionalError> {
+    let parser = Parser {
+        words,
+        pos: 0,
+        syntax: Syntax::Conditional,
+    };
+
+    parser.parse()?.evaluate(shell, Syntax::Conditional)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{ConditionalError, Expr};
+    use crate::shell::Shell;
+    use crate::testutil::TempDir;
+
+    fn test(args: &str) -> Result<bool, ConditionalError> {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        super::test(&mut Shell::default(), &args)
+    }
+
+    #[test]
+    fn test_parse_test() {
+        fn parse(args: &str) -> Result<Expr<'_>, ConditionalError> {
+            super::parse_test(&args.split(' ').collect::<Vec<&str>>())
+        }
+        assert_eq!(parse("!"), Ok(Expr::String("!")));
+        assert_eq!(parse("-n"), Ok(Expr::String("-n")));
+        assert_eq!(parse("! -z"), Ok(Expr::Not(Box::new(Expr::String("-z")))));
+        assert_eq!(parse("( = )"), Ok(Expr::Binary("(", "=", ")")));
+        assert_eq!(parse("! = !"), Ok(Expr::Binary("!", "=", "!")));
+        assert_eq!(parse("( ! )"), Ok(Expr::String("!")));
+        assert_eq!(
+            parse("! -n a"),
+            Ok(Expr::Not(Box::new(Expr::Unary("-n", "a"))))
+        );
+        assert_eq!(
+            parse("a -o b -a ! c"),
+            Ok(Expr::Or(
+                Box::new(Expr::String("a")),
+                Box::new(Expr::And(
+                    Box::new(Expr::String("b")),
+                    Box::new(Expr::Not(Box::new(Expr::String("c"))))
+                ))
+            ))
+        );
+        assert!(parse("( a b c").is_err());
+        assert!(parse("a b").is_err());
+    }
+
+    #[test]
+    fn test_test() {
+        assert_eq!(test(""), Ok(false));
+        assert_eq!(test("a"), Ok(true));
+        assert_eq!(test("-z a"), Ok(false));
+        assert_eq!(test("abc = abc"), Ok(true));
+        assert_eq!(test("abc != abc"), Ok(false));
+        assert_eq!(test("a < b"), Ok(true));
+        assert_eq!(test("10 -gt 9"), Ok(true));
+        assert_eq!(test("-1 -le -2"), Ok(false));
+        assert_eq!(
+            test("1 -eq x"),
+            Err(ConditionalError::Integer("x".to_owned()))
+        );
+        assert_eq!(test("( 1 -eq 2 -o a ) -a ! -z b"), Ok(true));
+        assert_eq!(test("a -a -z b"), Ok(false));
+        assert!(test("a -x").is_err());
+    }
+
+    #[test]
+    fn test_file_tests() {
+        let dir = TempDir::new("cond");
+        let old = dir.join("old");
+        fs::write(&old, "").unwrap();
+        let new = dir.join("new");
+        fs::write(&new, "x").unwrap();
+        let time = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&new, &link).unwrap();
+
+        let test = |args: String| test(&args).unwrap();
+        let (dir, old, new, link) = (dir.display(), old.display(), new.display(), link.display());
+        assert!(test(format!("-d {}", dir)));
+        assert!(!test(format!("-f {}", dir)));
+        assert!(test(format!("-f {} -a -e {}", old, new)));
+        assert!(!test(format!("-s {}", old)));
+        assert!(test(format!("-s {}", new)));
+        assert!(test(format!("-L {} -a -f {}", link, link)));
+        assert!(!test(format!("-L {}", new)));
+        assert!(test(format!("-r {} -a -w {}", new, new)));
+        assert!(!test(format!("-x {}", new)));
+        assert!(test(format!("{} -nt {}", new, old)));
+        assert!(test(format!("{} -ot {}", old, new)));
+        assert!(test(format!("{} -nt {}/missing", old, dir)));
+        assert!(test(format!("{} -ef {}", link, new)));
+        assert!(!test(format!("-e {}/missing", dir)));
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut shell = Shell::default();
+        shell.set_var("A", "a b*");
+        let mut conditional = |words: &[&str]| super::conditional(&mut shell, words);
+
+        assert_eq!(
+            conditional(&["-n", "$A", "&&", "$A", "==", "'a b*'"]),
+            Ok(true)
+        );
+        assert_eq!(conditional(&["$A", "==", "a*"]), Ok(true));
+        assert_eq!(conditional(&["$A", "==", "'a'*"]), Ok(true));
+        assert_eq!(conditional(&["$A", "==", "\"a*\""]), Ok(false));
+        assert_eq!(conditional(&["$A", "!=", "*c"]), Ok(true));
+        assert_eq!(
+            conditional(&["(", "-z", "$A", "||", "$NONE", ")", "&&", "x"]),
+            Ok(false)
+        );
+        assert_eq!(conditional(&["!", "$A", "<", "b"]), Ok(false));
+        assert_eq!(
+            conditional(&["$A", "-a", "x"]),
+            Err(ConditionalError::Syntax(
+                "-a: unexpected argument".to_owned()
+            ))
+        );
+        assert!(conditional(&[]).is_err());
+        assert_eq!(conditional(&["a", "=~", "'a.'"]), Ok(false));
+        assert_eq!(
+            conditional(&["a", "=~", "("]),
+            Err(ConditionalError::Regex("(".to_owned()))
+        );
+
+        assert_eq!(conditional(&["$A", "=~", "^(a)( (b))?[*]$"]), Ok(true));
+        assert_eq!(shell.dynamic_var("BASH_REMATCH").as_deref(), Some("a b*"));
+        assert_eq!(shell.dynamic_var("BASH_REMATCH[2]").as_deref(), Some(" b"));
+        assert_eq!(shell.dynamic_var("BASH_REMATCH[3]").as_deref(), Some("b"));
+        assert_eq!(shell.dynamic_var("BASH_REMATCH[4]"), None);
+        assert_eq!(
+            super::conditional(&mut shell, &["x", "=~", "(y)|[(]"]),
+            Ok(false)
+        );
+        assert!(shell.rematch.is_empty());
+    }
+
+    #[test]
+    fn test_subexpressions() {
+        assert_eq!(super::subexpressions("a(b)(c(d))"), 3);
+        assert_eq!(super::subexpressions("\\(a[(]"), 0);
+        assert_eq!(super::subexpressions("[]()](x)"), 1);
+    }
+}
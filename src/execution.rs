@@ -1,23 +1,55 @@
 //! Execute commands
 
+use std::collections::BTreeMap;
+use std::env;
 use std::error::Error;
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
 use std::process::exit;
 use std::ptr::{null, null_mut};
 
-use crate::parser::Command;
+use crate::builtins::{self, Builtin};
+use crate::expansion::{expand_assignment, expand_word, quote, ExpansionError};
+use crate::hash::{find_command, search_command};
+use crate::jobs::Jobs;
+use crate::parser::{AndOrList, Command, Connector, Pipeline};
+use crate::prompt::expand_prompt;
+use crate::script::execute_file;
+use crate::shell::Shell;
+use crate::sig::init_signal_pipe;
+use crate::trap::{
+    handle_pending_signals, reset_traps, restore_signals, run_trap, signals_to_restore, Condition,
+};
 
 use libc::{
-    __errno_location, c_char, c_int, close, dup2, execvp, fork, getpgid, getpgrp, getpid, open,
-    pid_t, pipe, setpgid, strerror, waitpid, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY, STDOUT_FILENO,
-    S_IRUSR, S_IWUSR, WNOHANG,
+    __errno_location, _exit, c_char, c_int, c_short, c_uint, close, dup2, execv, fcntl, fork,
+    getpgrp, open, pid_t, pipe2, posix_spawn, posix_spawn_file_actions_addclose,
+    posix_spawn_file_actions_adddup2, posix_spawn_file_actions_destroy,
+    posix_spawn_file_actions_init, posix_spawn_file_actions_t, posix_spawnattr_destroy,
+    posix_spawnattr_init, posix_spawnattr_setflags, posix_spawnattr_setpgroup,
+    posix_spawnattr_setsigdefault, posix_spawnattr_setsigmask, posix_spawnattr_t, setpgid,
+    sigaddset, sigemptyset, sigset_t, strerror, tcsetpgrp, waitpid, EACCES, EINTR, ENOENT, ENOEXEC,
+    F_DUPFD_CLOEXEC, F_SETFD, O_APPEND, O_CLOEXEC, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY,
+    POSIX_SPAWN_SETPGROUP, POSIX_SPAWN_SETSIGDEF, POSIX_SPAWN_SETSIGMASK, SIGINT, STDIN_FILENO,
+    STDOUT_FILENO, WEXITSTATUS, WIFEXITED, WIFSIGNALED, WIFSTOPPED, WSTOPSIG, WTERMSIG, WUNTRACED,
 };
 
 #[derive(Debug)]
 pub enum ExecutionError {
     Syscall(c_int),
-    Precondition,
+    Expansion(ExpansionError),
+    AmbiguousRedirect(String),
+    Noclobber(String),
+    CommandNotFound(String),
+    /// A file to redirect to could not be opened
+    Open(String, c_int),
 }
 
 impl Display for ExecutionError {
@@ -30,147 +62,1338 @@ impl Display for ExecutionError {
                     .to_string_lossy()
                     .into_owned()
             ),
-            ExecutionError::Precondition => write!(f, "precondition not fulfilled"),
+            ExecutionError::Expansion(e) => write!(f, "{}", e),
+            ExecutionError::AmbiguousRedirect(word) => write!(f, "{}: ambiguous redirect", word),
+            ExecutionError::Noclobber(filename) => {
+                write!(f, "{}: cannot overwrite existing file", filename)
+            }
+            ExecutionError::CommandNotFound(name) => write!(f, "{}: command not found", name),
+            ExecutionError::Open(filename, error_num) => write!(
+                f,
+                "{}: {}",
+                filename,
+                unsafe { CStr::from_ptr(strerror(*error_num)) }.to_string_lossy()
+            ),
         }
     }
 }
 
 impl Error for ExecutionError {}
 
-fn wait_foreground(pid: pid_t) -> Result<(), ExecutionError> {
-    if unsafe { waitpid(pid, null_mut(), 0) } == -1 {
-        // panic!("waitpid last foreground process failed");
-        return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+impl From<ExpansionError> for ExecutionError {
+    fn from(e: ExpansionError) -> Self {
+        ExecutionError::Expansion(e)
     }
+}
 
-    unsafe { waitpid(-getpgrp(), null_mut(), WNOHANG) };
+/// Convert a status as reported by `waitpid` into an exit status as
+/// seen by `$?`.
+pub(crate) fn exit_status(status: c_int) -> c_int {
+    if WIFEXITED(status) {
+        WEXITSTATUS(status)
+    } else if WIFSIGNALED(status) {
+        128 + WTERMSIG(status)
+    } else {
+        status
+    }
+}
 
-    Ok(())
+/// Wait for `pid` to terminate, or to stop with `WUNTRACED` in
+/// `options`, and return its status as reported by `waitpid`.
+fn wait_pid(pid: pid_t, options: c_int) -> Result<c_int, ExecutionError> {
+    let mut status = 0;
+    while unsafe { waitpid(pid, &mut status, options) } == -1 {
+        let error_num = unsafe { *__errno_location() };
+        if error_num != EINTR {
+            return Err(ExecutionError::Syscall(error_num));
+        }
+    }
+
+    Ok(status)
 }
 
-pub(crate) fn execute(cmds: &[Command], _background: bool) -> Result<(), ExecutionError> {
-    if cmds.is_empty() {
-        return Err(ExecutionError::Precondition);
+/// Lowest file descriptor used to save the ones redirected for builtins,
+/// out of the way of those used by scripts
+const SAVED_FD_MIN: c_int = 10;
+
+/// Run `builtin` within the shell process, with its standard file
+/// descriptors temporarily redirected according to `redirections`.
+fn run_builtin(
+    shell: &mut Shell,
+    builtin: Builtin,
+    argv: &[String],
+    redirections: &[(c_int, c_int)],
+) -> Result<c_int, ExecutionError> {
+    let mut saved_fds = Vec::with_capacity(redirections.len());
+    for &(fd, target_fd) in redirections {
+        let saved_fd = unsafe { fcntl(target_fd, F_DUPFD_CLOEXEC, SAVED_FD_MIN) };
+        if saved_fd != -1 {
+            saved_fds.push((saved_fd, target_fd));
+        }
+        if saved_fd == -1 || unsafe { dup2(fd, target_fd) } == -1 {
+            let error = ExecutionError::Syscall(unsafe { *__errno_location() });
+            // the redirections applied so far are undone
+            let _ = restore_fds(saved_fds);
+            return Err(error);
+        }
     }
 
-    let cmd = &cmds[0];
+    let status = builtin(shell, argv);
+    let _ = io::stdout().flush();
+    restore_fds(saved_fds)?;
 
-    let mut filedes: [c_int; 2] = [-1, -1];
-    let mut in_fd: Option<c_int> = None;
-    let mut out_fd: Option<c_int> = None;
-    let mut pgid = 0;
+    Ok(status)
+}
+
+/// Put the file descriptors saved by `run_builtin` back in place, in
+/// reverse order, and close the copies. All of them are restored even
+/// if one fails, the first error is returned.
+fn restore_fds(saved_fds: Vec<(c_int, c_int)>) -> Result<(), ExecutionError> {
+    let mut result = Ok(());
+    for (saved_fd, target_fd) in saved_fds.into_iter().rev() {
+        if unsafe { dup2(saved_fd, target_fd) } == -1 && result.is_ok() {
+            result = Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+        }
+        unsafe { close(saved_fd) };
+    }
+
+    result
+}
+
+/// A command ready to run: its expanded words, the builtin or the
+/// executable to run and the opened files of its redirections.
+struct PreparedCommand {
+    argv: Vec<String>,
+    builtin: Option<Builtin>,
+    path: Option<CString>,
+    /// pairs of an opened file and the file descriptor it replaces
+    redirections: Vec<(c_int, c_int)>,
+    /// names and values of the variables assigned for the command
+    assignments: Vec<(String, String)>,
+    /// the shell's ends of the pipes of process substitutions, which
+    /// the command inherits
+    substitutions: Vec<c_int>,
+}
+
+impl PreparedCommand {
+    /// Close the files opened for the command in the shell process.
+    fn close_redirections(&self) {
+        for &(fd, _) in &self.redirections {
+            unsafe { close(fd) };
+        }
+        for &fd in &self.substitutions {
+            unsafe { close(fd) };
+        }
+    }
+}
+
+fn expand_filename(shell: &Shell, word: &str) -> Result<String, ExecutionError> {
+    let mut fields = expand_word(shell, word)?;
+    if fields.len() != 1 {
+        return Err(ExecutionError::AmbiguousRedirect(word.to_owned()));
+    }
+
+    Ok(fields.remove(0))
+}
+
+/// Open `filename` for a redirection. The file descriptor is closed on
+/// `exec`, the copies made of it for the command are not. New files
+/// are created with the permissions the umask allows.
+fn open_file(filename: &str, flags: c_int) -> Result<c_int, ExecutionError> {
+    let c_filename = CString::new(filename).unwrap();
+    let fd = unsafe { open(c_filename.as_ptr(), flags | O_CLOEXEC, 0o666 as c_uint) };
+    if fd == -1 {
+        return Err(ExecutionError::Open(filename.to_owned(), unsafe {
+            *__errno_location()
+        }));
+    }
+
+    Ok(fd)
+}
 
-    if let Some(filename) = cmd.input_file {
-        let filename = CString::new(filename).unwrap();
-        in_fd = Some(unsafe { open(filename.as_ptr(), O_RDONLY) });
+/// Open the files `cmd` redirects to, adding them to `redirections`.
+fn open_redirections(
+    shell: &mut Shell,
+    cmd: &Command,
+    redirections: &mut Vec<(c_int, c_int)>,
+) -> Result<(), ExecutionError> {
+    if let Some(ref input_redirect) = cmd.input_file {
+        let filename = expand_filename(shell, input_redirect.filename)?;
+        redirections.push((
+            open_file(&filename, O_RDONLY)?,
+            c_int::from(input_redirect.file_descriptor),
+        ));
     }
 
     if let Some(ref output_redirect) = cmd.output_file {
-        let filename = CString::new(output_redirect.filename).unwrap();
-        out_fd = Some(unsafe {
-            open(
-                filename.as_ptr(),
-                O_CREAT
-                    | O_WRONLY
-                    | (if output_redirect.append {
-                        O_APPEND
-                    } else {
-                        O_TRUNC
-                    }),
-                S_IRUSR | S_IWUSR,
+        let filename = expand_filename(shell, output_redirect.filename)?;
+        if shell.options.noclobber
+            && !output_redirect.append
+            && !output_redirect.clobber
+            && fs::metadata(&filename).is_ok_and(|metadata| metadata.is_file())
+        {
+            return Err(ExecutionError::Noclobber(filename));
+        }
+
+        let mode = if output_redirect.append {
+            O_APPEND
+        } else {
+            O_TRUNC
+        };
+        redirections.push((
+            open_file(&filename, O_CREAT | O_WRONLY | mode)?,
+            c_int::from(output_redirect.file_descriptor),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Expand the assignments `words` one after the other, each one seeing
+/// the variables assigned before. They are not assigned yet, though.
+fn expand_assignments(
+    shell: &mut Shell,
+    words: &[&str],
+) -> Result<Vec<(String, String)>, ExecutionError> {
+    let mut assignments = Vec::with_capacity(words.len());
+    let mut saved_vars = Vec::with_capacity(words.len());
+    let mut result = Ok(());
+    for word in words {
+        match expand_assignment(shell, word) {
+            Ok((name, value)) => {
+                saved_vars.push((name.clone(), shell.var(&name).map(str::to_owned)));
+                shell.set_var(&name, &value);
+                assignments.push((name, value));
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    restore_variables(shell, saved_vars);
+    result?;
+
+    Ok(assignments)
+}
+
+/// Give variables back the values saved before, unsetting those which
+/// were not set.
+fn restore_variables(shell: &mut Shell, saved_vars: Vec<(String, Option<String>)>) {
+    for (name, value) in saved_vars.into_iter().rev() {
+        match value {
+            Some(value) => shell.set_var(&name, &value),
+            None => shell.unset_var(&name),
+        }
+    }
+}
+
+/// Assign the variables of `prepared`, a builtin executed by the shell
+/// itself. The assignments of special builtins and of commands without
+/// words stay in effect. For all others, return the previous values to
+/// restore once the builtin finished.
+fn assign_variables(
+    shell: &mut Shell,
+    prepared: &PreparedCommand,
+) -> Vec<(String, Option<String>)> {
+    let permanent = prepared
+        .argv
+        .first()
+        .is_none_or(|name| builtins::is_special(name));
+
+    let mut saved_vars = Vec::new();
+    for (name, value) in &prepared.assignments {
+        if !permanent {
+            saved_vars.push((name.clone(), shell.var(name).map(str::to_owned)));
+        }
+        shell.set_var(name, value);
+    }
+
+    saved_vars
+}
+
+/// Stands in for a command whose words all expanded to nothing, only
+/// its redirections take effect.
+fn empty_command(_shell: &mut Shell, _argv: &[String]) -> c_int {
+    0
+}
+
+/// Expand the words and assignments of `cmd`, open the files it
+/// redirects to and look up the command. A leading `command` without
+/// options is dropped, it only runs the command which follows. The
+/// words of `[[ ]]` are left to the conditional to expand.
+fn prepare(shell: &mut Shell, cmd: &Command) -> Result<PreparedCommand, ExecutionError> {
+    let words = iter::once(&cmd.name).chain(cmd.parameters.iter());
+    let conditional = cmd.name == "[[";
+    let mut argv = Vec::new();
+    if conditional {
+        argv.extend(words.map(|word| word.to_string()));
+    } else {
+        for word in words {
+            argv.extend(expand_word(shell, word)?);
+        }
+    }
+    while argv.len() > 1 && argv[0] == "command" && !argv[1].starts_with('-') {
+        argv.remove(0);
+    }
+    let builtin = match argv.first() {
+        _ if conditional => Some(builtins::conditional_command as Builtin),
+        Some(name) => builtins::lookup(name),
+        None => Some(empty_command as Builtin),
+    };
+    let assignments = expand_assignments(shell, &cmd.assignments)?;
+
+    let mut prepared = PreparedCommand {
+        argv,
+        builtin,
+        path: None,
+        redirections: Vec::new(),
+        assignments,
+        substitutions: Vec::new(),
+    };
+    if let Err(e) = open_redirections(shell, cmd, &mut prepared.redirections) {
+        prepared.close_redirections();
+        return Err(e);
+    }
+    if prepared.builtin.is_none() {
+        // a PATH assigned for the command only applies to its lookup,
+        // the commands remembered for the shell's PATH do not
+        let path_assignment = prepared
+            .assignments
+            .iter()
+            .rev()
+            .find(|(name, _)| name == "PATH");
+        let found = match path_assignment {
+            Some((_, path)) => search_command(path, &prepared.argv[0]),
+            None => find_command(shell, &prepared.argv[0]),
+        };
+        match found {
+            Some(path) => {
+                prepared.path = Some(CString::new(path.into_os_string().into_vec()).unwrap())
+            }
+            None => {
+                prepared.close_redirections();
+                return Err(ExecutionError::CommandNotFound(prepared.argv.remove(0)));
+            }
+        }
+    }
+
+    Ok(prepared)
+}
+
+/// The command as printed by the xtrace option: its assignments
+/// followed by its words, quoted such that they can be read back.
+fn trace_line(assignments: &[(String, String)], argv: &[String]) -> String {
+    let words: Vec<String> = assignments
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, quote(value)))
+        .chain(argv.iter().map(|arg| quote(arg)))
+        .collect();
+
+    words.join(" ")
+}
+
+/// Print the command about to be executed for the xtrace option,
+/// prefixed by the expansion of `PS4`. An empty command without
+/// assignments is not printed.
+fn trace(shell: &mut Shell, assignments: &[(String, String)], argv: &[String]) {
+    if assignments.is_empty() && argv.is_empty() {
+        return;
+    }
+
+    let ps4 = shell.var("PS4").unwrap_or_default().to_owned();
+    let prompt = expand_prompt(shell, &ps4);
+    eprintln!("{}{}", prompt.display, trace_line(assignments, argv));
+}
+
+/// Terminate the shell with exit status `status`, after executing the
+/// `EXIT` trap. An interactive shell sends `SIGHUP` to its jobs.
+pub(crate) fn exit_shell(shell: &mut Shell, status: c_int) -> ! {
+    shell.last_status = status;
+    run_trap(shell, Condition::Exit);
+    if shell.interactive {
+        shell.jobs.hang_up();
+    }
+    let _ = io::stdout().flush();
+    exit(status);
+}
+
+/// Whether the file at `path` looks like a binary rather than a
+/// script, i.e. its first line contains a NUL byte.
+fn is_binary_file(path: &Path) -> bool {
+    let mut buf = [0u8; 80];
+    let len = match File::open(path).and_then(|mut file| file.read(&mut buf)) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+    buf[..len]
+        .iter()
+        .take_while(|&&byte| byte != b'\n')
+        .any(|&byte| byte == 0)
+}
+
+/// Replace the child process by the executable at `path`. If that
+/// fails, report the error and exit with status 127 if the file does
+/// not exist, 126 otherwise. Text files the kernel cannot execute are
+/// executed as shell scripts instead, like POSIX shells do.
+fn exec_command(shell: &mut Shell, path: &CStr, argv: Vec<String>) -> ! {
+    let c_argv: Vec<CString> = argv
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).unwrap())
+        .collect();
+    let mut argv_ptrs: Vec<*const c_char> = c_argv.iter().map(|arg| arg.as_ptr()).collect();
+    argv_ptrs.push(null());
+    unsafe { execv(path.as_ptr(), argv_ptrs.as_ptr()) };
+
+    let error_num = unsafe { *__errno_location() };
+    let name = &argv[0];
+    let file = Path::new(OsStr::from_bytes(path.to_bytes()));
+    let status = match error_num {
+        ENOENT => {
+            eprintln!("stsh: {}: {}", name, ExecutionError::Syscall(error_num));
+            127
+        }
+        EACCES if file.is_dir() => {
+            eprintln!("stsh: {}: Is a directory", name);
+            126
+        }
+        ENOEXEC if is_binary_file(file) => {
+            eprintln!(
+                "stsh: {}: cannot execute binary file: {}",
+                name,
+                ExecutionError::Syscall(error_num)
+            );
+            126
+        }
+        ENOEXEC => {
+            shell.interactive = false;
+            shell.name = name.to_owned();
+            shell.positional = argv[1..].to_vec();
+            match execute_file(shell, file) {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("stsh: {}: {}", name, e);
+                    126
+                }
+            }
+        }
+        _ => {
+            eprintln!("stsh: {}: {}", name, ExecutionError::Syscall(error_num));
+            126
+        }
+    };
+
+    let _ = io::stdout().flush();
+    unsafe { _exit(status) };
+}
+
+extern "C" {
+    static environ: *const *mut c_char;
+}
+
+/// The environment of a command with the variable `assignments`: that
+/// of the shell, with the assigned variables added or replaced. Empty
+/// if there are no assignments, the environment of the shell is used
+/// as it is then.
+fn command_environment(assignments: &[(String, String)]) -> Vec<CString> {
+    if assignments.is_empty() {
+        return Vec::new();
+    }
+
+    let mut vars: BTreeMap<OsString, OsString> = env::vars_os().collect();
+    for (name, value) in assignments {
+        vars.insert(name.into(), value.into());
+    }
+
+    vars.into_iter()
+        .map(|(name, value)| {
+            let mut var = name.into_vec();
+            var.push(b'=');
+            var.extend(value.into_vec());
+            CString::new(var).unwrap()
+        })
+        .collect()
+}
+
+/// Start the external command `prepared` with `posix_spawn`, which is
+/// much cheaper than `fork` for a shell using a lot of memory. The
+/// child gets the same file descriptors and signal dispositions as a
+/// forked child would: `pipe_fds` are the ends of the pipes to read
+/// from, to write to and to close. A background command is put into
+/// process group `pgid`.
+///
+/// Return the error number if this fails, e.g. because the file is a
+/// script without shebang. Then the command is to be started by
+/// `fork`, which handles all cases.
+fn spawn_command(
+    shell: &Shell,
+    path: &CStr,
+    prepared: &PreparedCommand,
+    pipe_fds: [Option<c_int>; 3],
+    pgid: Option<pid_t>,
+) -> Result<pid_t, c_int> {
+    let [stdin_fd, stdout_fd, close_fd] = pipe_fds;
+
+    let mut file_actions = MaybeUninit::<posix_spawn_file_actions_t>::uninit();
+    let mut attr = MaybeUninit::<posix_spawnattr_t>::uninit();
+    unsafe {
+        posix_spawn_file_actions_init(file_actions.as_mut_ptr());
+        posix_spawnattr_init(attr.as_mut_ptr());
+    }
+    let mut file_actions = unsafe { file_actions.assume_init() };
+    let mut attr = unsafe { attr.assume_init() };
+
+    let mut result = 0;
+    let mut check = |error_num: c_int| {
+        if result == 0 {
+            result = error_num;
+        }
+    };
+
+    for (fd, target_fd) in [(stdin_fd, STDIN_FILENO), (stdout_fd, STDOUT_FILENO)] {
+        if let Some(fd) = fd {
+            check(unsafe { posix_spawn_file_actions_adddup2(&mut file_actions, fd, target_fd) });
+            check(unsafe { posix_spawn_file_actions_addclose(&mut file_actions, fd) });
+        }
+    }
+    if let Some(fd) = close_fd {
+        check(unsafe { posix_spawn_file_actions_addclose(&mut file_actions, fd) });
+    }
+    for &(fd, target_fd) in &prepared.redirections {
+        check(unsafe { posix_spawn_file_actions_adddup2(&mut file_actions, fd, target_fd) });
+        check(unsafe { posix_spawn_file_actions_addclose(&mut file_actions, fd) });
+    }
+    // duplicating a file descriptor onto itself clears its close-on-exec
+    // flag
+    for &fd in &prepared.substitutions {
+        check(unsafe { posix_spawn_file_actions_adddup2(&mut file_actions, fd, fd) });
+    }
+
+    let mut flags = POSIX_SPAWN_SETSIGMASK | POSIX_SPAWN_SETSIGDEF;
+    if let Some(pgid) = pgid {
+        flags |= POSIX_SPAWN_SETPGROUP;
+        check(unsafe { posix_spawnattr_setpgroup(&mut attr, pgid) });
+    }
+    let mut sigset = MaybeUninit::<sigset_t>::uninit();
+    unsafe { sigemptyset(sigset.as_mut_ptr()) };
+    check(unsafe { posix_spawnattr_setsigmask(&mut attr, sigset.as_ptr()) });
+    for signum in signals_to_restore(shell) {
+        unsafe { sigaddset(sigset.as_mut_ptr(), signum) };
+    }
+    check(unsafe { posix_spawnattr_setsigdefault(&mut attr, sigset.as_ptr()) });
+    check(unsafe { posix_spawnattr_setflags(&mut attr, flags as c_short) });
+
+    let argv: Vec<CString> = prepared
+        .argv
+        .iter()
+        .map(|arg| CString::new(arg.as_str()).unwrap())
+        .collect();
+    let mut argv_ptrs: Vec<*mut c_char> = argv.iter().map(|arg| arg.as_ptr() as *mut _).collect();
+    argv_ptrs.push(null_mut());
+    let envp = command_environment(&prepared.assignments);
+    let mut envp_ptrs: Vec<*mut c_char> = envp.iter().map(|var| var.as_ptr() as *mut _).collect();
+    envp_ptrs.push(null_mut());
+
+    let mut pid = 0;
+    if result == 0 {
+        result = unsafe {
+            posix_spawn(
+                &mut pid,
+                path.as_ptr(),
+                &file_actions,
+                &attr,
+                argv_ptrs.as_ptr(),
+                if prepared.assignments.is_empty() {
+                    environ
+                } else {
+                    envp_ptrs.as_ptr()
+                },
             )
-        });
+        };
     }
 
-    unsafe { pipe(filedes.as_mut_ptr()) };
+    unsafe {
+        posix_spawn_file_actions_destroy(&mut file_actions);
+        posix_spawnattr_destroy(&mut attr);
+    }
 
-    let pid = unsafe { fork() };
-    match pid {
-        -1 => Err(ExecutionError::Syscall(unsafe { *__errno_location() })),
-        0 => {
-            // child process
-            if let Some(in_fd) = in_fd {
-                if unsafe { dup2(in_fd, STDOUT_FILENO) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+    match result {
+        0 => Ok(pid),
+        error_num => Err(error_num),
+    }
+}
+
+/// Turn the result of a system call into an error if it failed.
+fn syscall(result: c_int) -> Result<c_int, ExecutionError> {
+    if result == -1 {
+        Err(ExecutionError::Syscall(unsafe { *__errno_location() }))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Connect the file descriptors of a forked child like `spawn_command`
+/// does and put it into process group `pgid`, if given.
+fn setup_child(
+    prepared: &PreparedCommand,
+    pipe_fds: [Option<c_int>; 3],
+    pgid: Option<pid_t>,
+) -> Result<(), ExecutionError> {
+    let [stdin_fd, stdout_fd, close_fd] = pipe_fds;
+
+    for (fd, target_fd) in [(stdin_fd, STDIN_FILENO), (stdout_fd, STDOUT_FILENO)] {
+        if let Some(fd) = fd {
+            syscall(unsafe { dup2(fd, target_fd) })?;
+            syscall(unsafe { close(fd) })?;
+        }
+    }
+    if let Some(fd) = close_fd {
+        syscall(unsafe { close(fd) })?;
+    }
+    for &(fd, target_fd) in &prepared.redirections {
+        syscall(unsafe { dup2(fd, target_fd) })?;
+        syscall(unsafe { close(fd) })?;
+    }
+    for &fd in &prepared.substitutions {
+        syscall(unsafe { fcntl(fd, F_SETFD, 0) })?;
+    }
+
+    if let Some(pgid) = pgid {
+        syscall(unsafe { setpgid(0, pgid) })?;
+    }
+
+    Ok(())
+}
+
+/// Execute `prepared` in a forked child. This never returns into the
+/// code of the shell: if setting up the child fails, it reports the
+/// error and exits with status 1. Like in a subshell, the traps of the
+/// shell are reset, e.g. `exit` in a pipeline does not run the `EXIT`
+/// trap. A foreground job takes over `terminal`, if given.
+fn run_child(
+    shell: &mut Shell,
+    prepared: PreparedCommand,
+    pipe_fds: [Option<c_int>; 3],
+    pgid: Option<pid_t>,
+    terminal: Option<c_int>,
+) -> ! {
+    // before SIGTTOU is restored, which would stop the child instead
+    if let (Some(pgid), Some(fd)) = (pgid, terminal) {
+        unsafe {
+            setpgid(0, pgid);
+            tcsetpgrp(fd, getpgrp());
+        }
+    }
+    reset_traps(shell);
+    restore_signals(shell);
+    shell.jobs = Jobs::default();
+    shell.job_control = false;
+    shell.terminal = None;
+
+    if let Err(e) = setup_child(&prepared, pipe_fds, pgid) {
+        let name = prepared.argv.first().map_or("command", String::as_str);
+        eprintln!("stsh: cannot start {}: {}", name, e);
+        unsafe { _exit(1) };
+    }
+    for (name, value) in &prepared.assignments {
+        shell.export_var(name, value);
+    }
+
+    if let Some(builtin) = prepared.builtin {
+        let status = builtin(shell, &prepared.argv);
+        let _ = io::stdout().flush();
+        unsafe { _exit(status) };
+    }
+
+    let path = prepared.path.expect("command without path");
+    exec_command(shell, &path, prepared.argv);
+}
+
+enum PipelineMember {
+    Process(pid_t),
+    /// The command already finished with this status without a
+    /// process of its own, e.g. a builtin run within the shell or a
+    /// command whose redirection failed
+    Finished(c_int),
+}
+
+/// Execute the commands of `pipeline`, each one in its own process
+/// connected by pipes. A single builtin is executed within the shell
+/// process. Background pipelines get their own process group, under
+/// job control all pipelines do. The `DEBUG` trap is executed before
+/// the pipeline. Foreground pipelines also wait for the processes of
+/// their process substitutions.
+///
+/// Under job control, a foreground pipeline gets the terminal and
+/// may be stopped, then it is added to the job table and its status
+/// is the one of a command killed by the stopping signal.
+fn execute_pipeline(shell: &mut Shell, pipeline: &Pipeline) -> Result<c_int, ExecutionError> {
+    run_trap(shell, Condition::Debug);
+
+    let background = pipeline.commands.last().is_some_and(|cmd| cmd.background);
+    let own_group = background || shell.job_control;
+    let terminal = shell.terminal.filter(|_| shell.job_control && !background);
+    let mut members = Vec::with_capacity(pipeline.commands.len());
+    let mut pgid = 0;
+    let mut stdin_fd: Option<c_int> = None;
+    let mut substitution_pids = Vec::new();
+
+    for cmd in &pipeline.commands {
+        let mut filedes: [c_int; 2] = [-1, -1];
+        if cmd.pipe && unsafe { pipe2(filedes.as_mut_ptr(), O_CLOEXEC) } == -1 {
+            return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+        }
+        let (next_stdin_fd, stdout_fd) = if cmd.pipe {
+            (Some(filedes[0]), Some(filedes[1]))
+        } else {
+            (None, None)
+        };
+
+        shell.lineno = shell.line_offset + cmd.line;
+        let prepared = prepare(shell, cmd);
+        let substitutions = shell.process_substitutions.take();
+        substitution_pids.extend(substitutions.iter().map(|&(pid, _)| pid));
+        let substitution_fds = substitutions.iter().map(|&(_, fd)| fd);
+        let prepared = match prepared {
+            Ok(prepared) => PreparedCommand {
+                substitutions: substitution_fds.collect(),
+                ..prepared
+            },
+            Err(e) => {
+                eprintln!("stsh: {}", e);
+                if matches!(e, ExecutionError::Expansion(_)) && !shell.interactive {
+                    exit_shell(shell, 1);
                 }
 
-                if unsafe { close(in_fd) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+                for fd in stdin_fd.iter().chain(stdout_fd.iter()).copied() {
+                    unsafe { close(fd) };
+                }
+                for fd in substitution_fds {
+                    unsafe { close(fd) };
                 }
+                stdin_fd = next_stdin_fd;
+                let status = match e {
+                    ExecutionError::CommandNotFound(_) => 127,
+                    _ => 1,
+                };
+                members.push(PipelineMember::Finished(status));
+                continue;
+            }
+        };
+
+        if shell.options.xtrace {
+            trace(shell, &prepared.assignments, &prepared.argv);
+        }
+        // `$_` is the last argument of the previous simple command
+        if pipeline.commands.len() == 1 {
+            if let Some(last) = prepared.argv.last() {
+                shell.set_var("_", last);
+            }
+        }
+
+        if let Some(builtin) = prepared.builtin {
+            if pipeline.commands.len() == 1 && !background {
+                let saved_vars = assign_variables(shell, &prepared);
+                let status = run_builtin(shell, builtin, &prepared.argv, &prepared.redirections);
+                restore_variables(shell, saved_vars);
+                prepared.close_redirections();
+                members.push(PipelineMember::Finished(status?));
+                break;
             }
+        }
+
+        // a spawned child cannot take over the terminal before it runs
+        let spawned = match prepared.path {
+            Some(ref path) if !shell.force_fork && terminal.is_none() => spawn_command(
+                shell,
+                path,
+                &prepared,
+                [stdin_fd, stdout_fd, next_stdin_fd],
+                own_group.then_some(pgid),
+            )
+            .ok(),
+            _ => None,
+        };
 
-            if let Some(out_fd) = out_fd {
-                if unsafe { dup2(out_fd, STDOUT_FILENO) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+        let pid = spawned.unwrap_or_else(|| unsafe { fork() });
+        match pid {
+            -1 => return Err(ExecutionError::Syscall(unsafe { *__errno_location() })),
+            0 => run_child(
+                shell,
+                prepared,
+                [stdin_fd, stdout_fd, next_stdin_fd],
+                own_group.then_some(pgid),
+                terminal,
+            ),
+            _ => {
+                // parent process
+                if own_group {
+                    // also done here to not race with the child, which
+                    // may fail if the child already called execvp
+                    unsafe { setpgid(pid, pgid) };
+                    if pgid == 0 {
+                        pgid = pid;
+                        if let Some(fd) = terminal {
+                            unsafe { tcsetpgrp(fd, pgid) };
+                        }
+                    }
                 }
 
-                if unsafe { close(out_fd) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+                for fd in stdin_fd.iter().chain(stdout_fd.iter()) {
+                    unsafe { close(*fd) };
                 }
-            }
+                stdin_fd = next_stdin_fd;
 
-            if cmd.background {
-                if pgid == 0 {
-                    if unsafe { setpgid(getpid(), 0) } == -1 {
+                for &(fd, _) in &prepared.redirections {
+                    if unsafe { close(fd) } == -1 {
                         return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
                     }
-                } else if unsafe { setpgid(getpid(), pgid) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
                 }
+                for &fd in &prepared.substitutions {
+                    unsafe { close(fd) };
+                }
+
+                members.push(PipelineMember::Process(pid));
             }
+        }
+    }
+
+    let status = if background {
+        let pids: Vec<pid_t> = members
+            .iter()
+            .filter_map(|member| match member {
+                PipelineMember::Process(pid) => Some(*pid),
+                PipelineMember::Finished(_) => None,
+            })
+            .collect();
+        if let Some(&pid) = pids.last() {
+            shell.jobs.add(pgid, &pids, pipeline.to_string());
+            shell.last_background_pid = Some(pid);
+        }
+        shell.substitution_pids.extend(substitution_pids);
+        0
+    } else {
+        let wait_options = if shell.job_control { WUNTRACED } else { 0 };
+        let mut status = 0;
+        let mut failed_status = 0;
+        let mut interrupted = false;
+        let mut stopped = None;
+        for (idx, member) in members.iter().enumerate() {
+            status = match *member {
+                PipelineMember::Process(pid) => {
+                    let wstatus = wait_pid(pid, wait_options)?;
+                    if WIFSTOPPED(wstatus) {
+                        stopped = Some((idx, WSTOPSIG(wstatus)));
+                        failed_status = 128 + WSTOPSIG(wstatus);
+                        status = failed_status;
+                        break;
+                    }
+                    interrupted |= WIFSIGNALED(wstatus) && WTERMSIG(wstatus) == SIGINT;
+                    exit_status(wstatus)
+                }
+                PipelineMember::Finished(status) => status,
+            };
+            if status != 0 {
+                failed_status = status;
+            }
+        }
+        if let Some(fd) = terminal {
+            unsafe { tcsetpgrp(fd, getpgrp()) };
+        }
 
-            let name = CString::new(cmd.name).unwrap();
-            let parameters: Vec<CString> = cmd
-                .parameters
+        if let Some((idx, signum)) = stopped {
+            // the members which did not finish yet belong to the job
+            let pids: Vec<pid_t> = members[idx..]
                 .iter()
-                .map(|param| CString::new(*param).unwrap())
+                .filter_map(|member| match member {
+                    PipelineMember::Process(pid) => Some(*pid),
+                    PipelineMember::Finished(_) => None,
+                })
                 .collect();
-            let mut argv: Vec<*const c_char> =
-                parameters.iter().map(|param| param.as_ptr()).collect();
-            argv.insert(0, name.as_ptr());
-            argv.push(null());
-            if unsafe { execvp(name.as_ptr(), argv.as_ptr()) } == -1 {
-                exit(unsafe { *__errno_location() });
+            let report = shell
+                .jobs
+                .add_stopped(pgid, &pids, pipeline.to_string(), signum);
+            shell.substitution_pids.extend(substitution_pids);
+            // the terminal echoed ^Z
+            if shell.interactive {
+                eprintln!("\n{}", report);
             }
+        } else {
+            for pid in substitution_pids {
+                wait_pid(pid, 0)?;
+            }
+        }
 
-            unreachable!("execvp");
+        // the terminal echoed ^C, start the next prompt on a new line
+        if interrupted && shell.interactive {
+            eprintln!();
         }
-        _ => {
-            // parent process
-            if cmd.background {
-                if pgid == 0 {
-                    pgid = pid;
-                }
-            } else {
-                wait_foreground(pid)?;
-            }
 
-            if let Some(out_fd) = out_fd {
-                if unsafe { close(out_fd) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+        if shell.options.pipefail {
+            failed_status
+        } else {
+            status
+        }
+    };
+
+    if pipeline.negated {
+        Ok(c_int::from(status == 0))
+    } else {
+        Ok(status)
+    }
+}
+
+/// Execute the pipelines of `and_or_list` depending on the exit status
+/// of their predecessor.
+///
+/// If the list fails, the `ERR` trap is executed and, with the errexit
+/// option, the shell exits, unless the failing pipeline is negated or
+/// followed by `&&` or `||`, or the status is the one of `return`.
+fn execute_and_or_list(
+    shell: &mut Shell,
+    and_or_list: &AndOrList,
+) -> Result<c_int, ExecutionError> {
+    let mut status = execute_pipeline(shell, &and_or_list.first)?;
+    shell.last_status = status;
+    let mut exempt = and_or_list.first.negated || !and_or_list.rest.is_empty();
+
+    for (idx, (connector, pipeline)) in and_or_list.rest.iter().enumerate() {
+        let run = match connector {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if run {
+            status = execute_pipeline(shell, pipeline)?;
+            shell.last_status = status;
+            exempt = pipeline.negated || idx + 1 < and_or_list.rest.len();
+        }
+    }
+
+    if status != 0 && !exempt && shell.pending_return.is_none() {
+        run_trap(shell, Condition::Err);
+        if shell.options.errexit {
+            exit_shell(shell, status);
+        }
+    }
+
+    Ok(status)
+}
+
+/// Execute `list` and return the exit status of the last command,
+/// which is also stored in `shell`. Signals which arrived in the
+/// meantime are handled after each and-or list. With the noexec
+/// option, commands of non-interactive shells are not executed at
+/// all.
+pub(crate) fn execute(shell: &mut Shell, list: &[AndOrList]) -> Result<c_int, ExecutionError> {
+    if shell.options.noexec && !shell.interactive {
+        return Ok(shell.last_status);
+    }
+
+    for and_or_list in list {
+        execute_and_or_list(shell, and_or_list)?;
+        handle_pending_signals(shell);
+    }
+
+    Ok(shell.last_status)
+}
+
+/// Execute `list` in a subshell, which is the current, just forked
+/// child process, and exit with its status.
+fn run_subshell(shell: &Shell, list: &[AndOrList]) -> ! {
+    // do not consume the signals meant for the parent
+    let _ = init_signal_pipe();
+    let mut subshell = shell.clone();
+    subshell.jobs = Jobs::default();
+    subshell.job_control = false;
+    subshell.terminal = None;
+    // keeping the pipes of process substitutions open would delay
+    // the end of their input
+    for (_, fd) in subshell.process_substitutions.take() {
+        unsafe { close(fd) };
+    }
+    subshell.substitution_pids.clear();
+    reset_traps(&mut subshell);
+    subshell.seed_random();
+    restore_signals(&subshell);
+    let status = execute(&mut subshell, list).unwrap_or(1);
+    unsafe { _exit(status) };
+}
+
+/// Execute `list` in a subshell and return what it wrote to standard
+/// output, without trailing newlines. This is the work horse of
+/// command substitution.
+pub(crate) fn capture_output(shell: &Shell, list: &[AndOrList]) -> Result<String, ExecutionError> {
+    let mut filedes: [c_int; 2] = [-1, -1];
+    if unsafe { pipe2(filedes.as_mut_ptr(), O_CLOEXEC) } == -1 {
+        return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+    }
+    let [read_fd, write_fd] = filedes;
+
+    let pid = unsafe { fork() };
+    match pid {
+        -1 => {
+            let error_num = unsafe { *__errno_location() };
+            unsafe {
+                close(read_fd);
+                close(write_fd);
+            }
+            Err(ExecutionError::Syscall(error_num))
+        }
+        0 => {
+            // child process
+            unsafe {
+                close(read_fd);
+                if dup2(write_fd, STDOUT_FILENO) == -1 {
+                    _exit(1);
                 }
+                close(write_fd);
             }
+            run_subshell(shell, list);
+        }
+        _ => {
+            // parent process
+            unsafe { close(write_fd) };
 
-            if let Some(in_fd) = in_fd {
-                if unsafe { close(in_fd) } == -1 {
-                    return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+            let mut output = Vec::new();
+            let read_result = unsafe { File::from_raw_fd(read_fd) }.read_to_end(&mut output);
+            wait_pid(pid, 0)?;
+            read_result.map_err(|e| ExecutionError::Syscall(e.raw_os_error().unwrap_or(0)))?;
+
+            let mut output = String::from_utf8_lossy(&output).into_owned();
+            output.truncate(output.trim_end_matches('\n').len());
+
+            Ok(output)
+        }
+    }
+}
+
+/// Start `list` in a subshell for a process substitution, connected to
+/// the shell by a pipe: the subshell writes to it, or with `output`
+/// reads from it. Return the process id and the shell's end of the
+/// pipe, which is moved out of the way of redirections.
+pub(crate) fn start_substitution(
+    shell: &Shell,
+    list: &[AndOrList],
+    output: bool,
+) -> Result<(pid_t, c_int), ExecutionError> {
+    let mut filedes: [c_int; 2] = [-1, -1];
+    if unsafe { pipe2(filedes.as_mut_ptr(), O_CLOEXEC) } == -1 {
+        return Err(ExecutionError::Syscall(unsafe { *__errno_location() }));
+    }
+    let [read_fd, write_fd] = filedes;
+    let (shell_fd, child_fd, target_fd) = if output {
+        (write_fd, read_fd, STDIN_FILENO)
+    } else {
+        (read_fd, write_fd, STDOUT_FILENO)
+    };
+
+    let pid = unsafe { fork() };
+    match pid {
+        -1 => {
+            let error_num = unsafe { *__errno_location() };
+            unsafe {
+                close(read_fd);
+                close(write_fd);
+            }
+            Err(ExecutionError::Syscall(error_num))
+        }
+        0 => {
+            // child process
+            unsafe {
+                close(shell_fd);
+                if dup2(child_fd, target_fd) == -1 {
+                    _exit(1);
                 }
+                close(child_fd);
             }
+            run_subshell(shell, list);
+        }
+        _ => {
+            // parent process
+            unsafe { close(child_fd) };
+            let fd = match unsafe { fcntl(shell_fd, F_DUPFD_CLOEXEC, SAVED_FD_MIN) } {
+                -1 => shell_fd,
+                fd => {
+                    unsafe { close(shell_fd) };
+                    fd
+                }
+            };
 
-            Ok(())
+            Ok((pid, fd))
         }
     }
 }
 
-pub(crate) fn catch_background_process(pid: pid_t) {
-    // Because this function is called from a signal handler, all
-    // functions called here need to be and are "async-signal-safe",
-    // see "man 7 signal-safety"
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::jobs::JobState;
+    use crate::parser;
+    use crate::shell::Shell;
+    use crate::testutil::{for_each_backend, TempDir};
+
+    fn run(shell: &mut Shell, line: &str) -> libc::c_int {
+        let (_, command_line) = parser::parse(line).unwrap();
+        super::execute(shell, &command_line.lists).unwrap()
+    }
+
+    #[test]
+    fn test_exec_errors() {
+        let dir = TempDir::new("exec");
+        let script = dir.join("script");
+        fs::write(&script, "exit $1\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let binary = dir.join("binary");
+        fs::write(&binary, b"\x7fELF\0\0\0").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        let data = dir.join("data");
+        fs::write(&data, "").unwrap();
+
+        let dir = dir.display();
+        for_each_backend(|shell| {
+            assert_eq!(run(shell, &format!("{}/script 7", dir)), 7);
+            assert_eq!(run(shell, &format!("{}/binary", dir)), 126);
+            assert_eq!(run(shell, &format!("{}/data", dir)), 126);
+            assert_eq!(run(shell, &format!("{}", dir)), 126);
+            assert_eq!(run(shell, &format!("{}/missing", dir)), 127);
+            assert_eq!(run(shell, "no-such-command"), 127);
+
+            // a file found in PATH which is not executable
+            shell.set_var("PATH", &format!("/usr/bin:/bin:{}", dir));
+            assert_eq!(run(shell, "data"), 126);
+        });
+    }
+
+    #[test]
+    fn test_spawn() {
+        let dir = TempDir::new("spawn");
+        let out = dir.join("out");
+
+        for_each_backend(|shell| {
+            let line = format!("printf 'a\\nb\\n' | grep b > {}", out.display());
+            assert_eq!(run(shell, &line), 0);
+            assert_eq!(fs::read_to_string(&out).unwrap(), "b\n");
+            assert_eq!(run(shell, "false | true"), 0);
+            assert_eq!(run(shell, "true | false"), 1);
+        });
+    }
+
+    #[test]
+    fn test_process_substitution() {
+        let dir = TempDir::new("procsub");
+        let out = dir.join("out");
+
+        for_each_backend(|shell| {
+            let line = format!("cat <(echo a) - < <(echo b) > {}", out.display());
+            assert_eq!(run(shell, &line), 0);
+            assert_eq!(fs::read_to_string(&out).unwrap(), "a\nb\n");
+            let line = format!("echo c > >(tr c C > {})", out.display());
+            assert_eq!(run(shell, &line), 0);
+            assert_eq!(fs::read_to_string(&out).unwrap(), "C\n");
+            assert_eq!(run(shell, "cmp -s <(echo a) <(echo b)"), 1);
+            assert!(shell.process_substitutions.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_assignments() {
+        let dir = TempDir::new("assign");
+        let out = dir.join("out");
+        let cmd = dir.join("cmd");
+        fs::write(&cmd, "#!/bin/sh\nexit 5\n").unwrap();
+        fs::set_permissions(&cmd, fs::Permissions::from_mode(0o755)).unwrap();
 
-    let child_pgid = unsafe { getpgid(pid) };
-    if (unsafe { getpgrp() } != child_pgid) && (child_pgid != -1) {
-        unsafe { waitpid(pid, null_mut(), WNOHANG) };
+        for_each_backend(|shell| {
+            assert_eq!(run(shell, "A=1 B=$A"), 0);
+            assert_eq!(shell.var("A"), Some("1"));
+            assert_eq!(shell.var("B"), Some("1"));
+
+            let line = format!("A=2 C=3 sh -c 'echo $A$B$C' > {}", out.display());
+            assert_eq!(run(shell, &line), 0);
+            assert_eq!(fs::read_to_string(&out).unwrap(), "23\n");
+            assert_eq!(shell.var("A"), Some("1"));
+            assert_eq!(shell.var("C"), None);
+            assert!(std::env::var_os("C").is_none());
+
+            // regular builtins get the variables temporarily, special
+            // ones keep them
+            assert_eq!(run(shell, "A=4 C=5 type type > /dev/null"), 0);
+            assert_eq!(shell.var("A"), Some("1"));
+            assert_eq!(shell.var("C"), None);
+            assert_eq!(run(shell, "A=6 set -- x"), 0);
+            assert_eq!(shell.var("A"), Some("6"));
+
+            // the command is looked up in a PATH assigned for it
+            assert_eq!(run(shell, &format!("PATH={} cmd", dir.display())), 5);
+            assert!(shell.hash.get("cmd").is_none());
+            assert_eq!(run(shell, "cmd"), 127);
+        });
+    }
+
+    #[test]
+    fn test_trace_line() {
+        let assignments = vec![
+            ("A".to_owned(), "1".to_owned()),
+            ("B".to_owned(), "x y".to_owned()),
+        ];
+        let argv = vec!["echo".to_owned(), "it's".to_owned()];
+        assert_eq!(
+            super::trace_line(&assignments, &argv),
+            "A=1 B='x y' echo 'it'\\''s'"
+        );
+        assert_eq!(super::trace_line(&assignments, &[]), "A=1 B='x y'");
+        assert_eq!(super::trace_line(&[], &argv), "echo 'it'\\''s'");
+    }
+
+    #[test]
+    fn test_traps_in_pipeline() {
+        let dir = TempDir::new("pipetrap");
+        let out = dir.join("out");
+
+        for_each_backend(|shell| {
+            let line = format!("trap 'echo bye > {}' EXIT", out.display());
+            assert_eq!(run(shell, &line), 0);
+            assert_eq!(run(shell, "exit 3 | true"), 0);
+            assert!(!out.exists());
+        });
+    }
+
+    #[test]
+    fn test_stopped_job() {
+        for_each_backend(|shell| {
+            shell.job_control = true;
+            assert_eq!(
+                run(shell, "true | sh -c 'kill -STOP $$'"),
+                128 + libc::SIGSTOP
+            );
+            assert!(shell.jobs.has_stopped());
+            let job = shell.jobs.get(0);
+            assert_eq!(job.state, JobState::Stopped(libc::SIGSTOP));
+            assert_eq!(job.command, "true | sh -c 'kill -STOP $$'");
+            // in a process group of its own, led by the first command
+            assert_eq!(job.processes.len(), 1);
+            assert_ne!(job.pgid, unsafe { libc::getpgrp() });
+
+            assert_eq!(run(shell, "kill -CONT %1; wait %1"), 0);
+            assert_eq!(shell.jobs.len(), 0);
+        });
     }
 
-    // we have no way to propagate errors
+    #[test]
+    fn test_redirection_errors() {
+        let dir = TempDir::new("redir");
+        let out = dir.join("out");
+
+        for_each_backend(|shell| {
+            let line = format!("cat < {}/missing > {}", dir.display(), out.display());
+            assert_eq!(run(shell, &line), 1);
+            assert!(!out.exists());
+            let line = format!("echo > {}/missing/out", dir.display());
+            assert_eq!(run(shell, &line), 1);
+
+            let umask = unsafe { libc::umask(0o027) };
+            assert_eq!(run(shell, &format!("true > {}", out.display())), 0);
+            unsafe { libc::umask(umask) };
+            let mode = fs::metadata(&out).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+            fs::remove_file(&out).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_builtin_redirection_errors() {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        let dir = TempDir::new("builtin-redir");
+        let target = fs::File::create(dir.join("target")).unwrap();
+        let out = fs::File::create(dir.join("out")).unwrap();
+        let inode = |fd| fs::metadata(format!("/dev/fd/{}", fd)).unwrap().ino();
+        // the first redirection succeeds, the second one replaces a
+        // file descriptor which is not open
+        let (target_fd, closed_fd) = (200, 201);
+        assert_eq!(
+            unsafe { libc::dup2(target.as_raw_fd(), target_fd) },
+            target_fd
+        );
+        let redirections = [(out.as_raw_fd(), target_fd), (out.as_raw_fd(), closed_fd)];
+
+        let mut shell = Shell::default();
+        let result = super::run_builtin(&mut shell, |_, _| 0, &[], &redirections);
+        assert!(result.is_err());
+        assert_eq!(inode(target_fd), target.metadata().unwrap().ino());
+        assert_eq!(unsafe { libc::fcntl(closed_fd, libc::F_GETFD) }, -1);
+        unsafe { libc::close(target_fd) };
+    }
+
+    /// The environment variable naming the case `child_error_case`
+    /// runs.
+    const CHILD_CASE: &str = "STSH_TEST_CHILD_CASE";
+
+    /// Not a test of its own: started by `test_child_errors` in a new
+    /// process, this process becomes the child of the case named by
+    /// `CHILD_CASE`. Forking the test harness instead could deadlock the
+    /// child on a lock held by another thread.
+    #[test]
+    fn child_error_case() {
+        let case = match std::env::var(CHILD_CASE) {
+            Ok(case) => case,
+            Err(_) => return,
+        };
+        // not an open file descriptor
+        let bad_fd = 1000;
+        let (redirections, pipe_fds, pgid) = match case.as_str() {
+            "none" => (vec![], [None; 3], None),
+            "redirection" => (vec![(bad_fd, 1)], [None; 3], None),
+            "stdin" => (vec![], [Some(bad_fd), None, None], None),
+            "stderr" => (vec![], [None, None, Some(bad_fd)], None),
+            // process group of another session
+            "pgid" => (vec![], [None; 3], Some(1)),
+            _ => panic!("unknown case {}", case),
+        };
+
+        let prepared = super::PreparedCommand {
+            argv: vec!["true".to_owned()],
+            builtin: None,
+            path: Some(std::ffi::CString::new("/bin/true").unwrap()),
+            redirections,
+            assignments: vec![],
+            substitutions: vec![],
+        };
+        super::run_child(&mut Shell::default(), prepared, pipe_fds, pgid, None);
+    }
+
+    /// Run `child_error_case` for `case` in a new process of the test
+    /// binary and return its exit status and what it wrote to stderr.
+    fn run_child_case(case: &str) -> (libc::c_int, String) {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "execution::tests::child_error_case"])
+            .args(["--nocapture", "--test-threads=1"])
+            .env(CHILD_CASE, case)
+            .output()
+            .unwrap();
+
+        (
+            output.status.code().unwrap(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_child_errors() {
+        let bad_fd = "stsh: cannot start true: Bad file descriptor\n";
+        assert_eq!(run_child_case("none"), (0, String::new()));
+        assert_eq!(run_child_case("redirection"), (1, bad_fd.to_owned()));
+        assert_eq!(run_child_case("stdin"), (1, bad_fd.to_owned()));
+        assert_eq!(run_child_case("stderr"), (1, bad_fd.to_owned()));
+        assert_eq!(
+            run_child_case("pgid"),
+            (
+                1,
+                "stsh: cannot start true: Operation not permitted\n".to_owned()
+            )
+        );
+    }
 }
@@ -0,0 +1,1079 @@
+//! Word expansion
+//!
+//! https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_02_06
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Display, Formatter};
+use std::mem::MaybeUninit;
+use std::ptr::null_mut;
+use std::slice;
+
+use libc::{c_char, getpwnam_r, getpwuid_r, getuid, glob, glob_t, globfree, passwd};
+
+use crate::execution::{capture_output, start_substitution};
+use crate::parser;
+use crate::shell::Shell;
+
+#[derive(Debug, PartialEq)]
+pub enum ExpansionError {
+    Unbound(String),
+}
+
+impl Display for ExpansionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpansionError::Unbound(name) => write!(f, "{}: unbound variable", name),
+        }
+    }
+}
+
+impl std::error::Error for ExpansionError {}
+
+/// Where a character of an expanded word comes from, which determines
+/// the further expansions it takes part in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Origin {
+    /// Unquoted text of the word itself
+    Literal,
+    /// Quoted or escaped text
+    Quoted,
+    /// Result of an unquoted expansion
+    Expansion,
+}
+
+fn is_name_start(chr: char) -> bool {
+    chr.is_ascii_alphabetic() || chr == '_'
+}
+
+fn is_name_char(chr: char) -> bool {
+    chr.is_ascii_alphanumeric() || chr == '_'
+}
+
+/// Look up the value of parameter `name`, which is either a variable
+/// name or a special parameter. Return `None` if it is not set.
+fn parameter(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "-" => Some(shell.flags()),
+        "!" => shell.last_background_pid.map(|pid| pid.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "@" => Some(shell.positional.join(" ")),
+        "*" => {
+            // joined by the first character of `IFS`
+            let separator = match shell.var("IFS") {
+                Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+                None => " ".to_owned(),
+            };
+            Some(shell.positional.join(&separator))
+        }
+        _ if name.chars().all(|c| c.is_ascii_digit()) => match name.parse::<usize>() {
+            Ok(0) => Some(shell.name.clone()),
+            Ok(n) => shell.positional.get(n - 1).cloned(),
+            _ => None,
+        },
+        _ => shell
+            .dynamic_var(name)
+            .or_else(|| shell.var(name).map(str::to_owned)),
+    }
+}
+
+/// Whether `s` is a valid variable name.
+pub(crate) fn is_name(s: &str) -> bool {
+    s.starts_with(is_name_start) && s.chars().all(is_name_char)
+}
+
+/// The home directory of user `name` from the user database, or of the
+/// current user if `name` is `None`.
+fn user_home(name: Option<&str>) -> Option<String> {
+    let mut pwd = MaybeUninit::<passwd>::uninit();
+    let mut buf = vec![0 as c_char; 16384];
+    let mut result = null_mut();
+    let ret = match name {
+        Some(name) => {
+            let name = CString::new(name).ok()?;
+            unsafe {
+                getpwnam_r(
+                    name.as_ptr(),
+                    pwd.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            }
+        }
+        None => unsafe {
+            getpwuid_r(
+                getuid(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        },
+    };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+
+    let dir = unsafe { CStr::from_ptr((*result).pw_dir) };
+    Some(dir.to_string_lossy().into_owned())
+}
+
+/// Expand the tilde prefix `~prefix`: `~` is the home directory, `~+`
+/// and `~-` are the current and previous working directory and `~user`
+/// is the home directory of that user. Return `None` if the prefix is
+/// to be taken literally.
+fn tilde_expansion(shell: &Shell, prefix: &str) -> Option<String> {
+    match prefix {
+        "" => shell
+            .var("HOME")
+            .map(str::to_owned)
+            .or_else(|| user_home(None)),
+        "+" => shell.var("PWD").map(str::to_owned),
+        "-" => shell.var("OLDPWD").map(str::to_owned),
+        _ => user_home(Some(prefix)),
+    }
+}
+
+fn command_substitution(shell: &Shell, command: &str) -> String {
+    let command = parser::expand_aliases(command, &shell.aliases);
+    match parser::parse(&command) {
+        Ok((_rest, command_line)) => {
+            capture_output(shell, &command_line.lists).unwrap_or_else(|e| {
+                eprintln!("stsh: command substitution: {}", e);
+                String::new()
+            })
+        }
+        Err(e) => {
+            eprintln!("stsh: command substitution: {}", parser::syntax_error(&e));
+            String::new()
+        }
+    }
+}
+
+/// Start the process substitution `<(command)`, or `>(command)` with
+/// `output`, and return the path of the pipe connected to it. The
+/// shell's end of the pipe is recorded in `process_substitutions` to be
+/// kept open for the command being expanded.
+fn process_substitution(shell: &Shell, command: &str, output: bool) -> String {
+    let command = parser::expand_aliases(command, &shell.aliases);
+    let started = match parser::parse(&command) {
+        Ok((_rest, command_line)) => {
+            start_substitution(shell, &command_line.lists, output).map_err(|e| e.to_string())
+        }
+        Err(e) => Err(parser::syntax_error(&e)),
+    };
+
+    match started {
+        Ok((pid, fd)) => {
+            shell.process_substitutions.borrow_mut().push((pid, fd));
+            format!("/dev/fd/{}", fd)
+        }
+        Err(e) => {
+            eprintln!("stsh: process substitution: {}", e);
+            String::new()
+        }
+    }
+}
+
+/// Expand the parameter or command substitution at the beginning of
+/// `input`, which starts right after a `$`. Return the expansion and
+/// the number of bytes consumed, or `None` if the `$` is to be taken
+/// literally. With the nounset option, the expansion of an unset
+/// parameter is an error.
+pub(crate) fn expand_dollar(
+    shell: &Shell,
+    input: &str,
+) -> Result<Option<(String, usize)>, ExpansionError> {
+    let (name, len) = match input.chars().next() {
+        Some('(') => {
+            return Ok(parser::substitution_len(&input[1..])
+                .map(|len| (command_substitution(shell, &input[1..=len]), len + 2)));
+        }
+        Some('{') => match input[1..].find('}') {
+            Some(len) => (&input[1..=len], len + 2),
+            None => return Ok(None),
+        },
+        Some(chr)
+            if matches!(chr, '?' | '#' | '-' | '!' | '$' | '@' | '*') || chr.is_ascii_digit() =>
+        {
+            (&input[..1], 1)
+        }
+        Some(chr) if is_name_start(chr) => {
+            let len = input.find(|c| !is_name_char(c)).unwrap_or(input.len());
+            (&input[..len], len)
+        }
+        _ => return Ok(None),
+    };
+
+    match parameter(shell, name) {
+        Some(value) => Ok(Some((value, len))),
+        None if shell.options.nounset => Err(ExpansionError::Unbound(name.to_owned())),
+        None => Ok(Some((String::new(), len))),
+    }
+}
+
+/// The byte offsets of the braces and commas in `word` which take part
+/// in brace expansion, i.e. those which are not quoted or part of a
+/// parameter expansion or command substitution.
+fn brace_positions(word: &str) -> Vec<(usize, char)> {
+    let mut positions = Vec::new();
+    let mut in_double_quotes = false;
+    let mut idx = 0;
+    while let Some(chr) = word[idx..].chars().next() {
+        let rest = &word[idx + chr.len_utf8()..];
+        let len = match chr {
+            '\\' => rest.chars().next().map_or(0, char::len_utf8),
+            '\'' if !in_double_quotes => rest.find('\'').map_or(rest.len(), |len| len + 1),
+            '"' => {
+                in_double_quotes = !in_double_quotes;
+                0
+            }
+            '$' if rest.starts_with('{') => rest.find('}').map_or(0, |len| len + 1),
+            '$' if rest.starts_with('(') => {
+                parser::substitution_len(&rest[1..]).map_or(0, |len| len + 2)
+            }
+            '<' | '>' if !in_double_quotes && rest.starts_with('(') => {
+                parser::substitution_len(&rest[1..]).map_or(0, |len| len + 2)
+            }
+            '{' | '}' | ',' if !in_double_quotes => {
+                positions.push((idx, chr));
+                0
+            }
+            _ => 0,
+        };
+        idx += chr.len_utf8() + len;
+    }
+
+    positions
+}
+
+/// Maximum number of words brace expansion makes of a single word.
+/// Words expanding to more, like `{1..9999999999}` or
+/// `{1..60000}{1..60000}`, are left as they are instead of exhausting
+/// memory.
+const MAX_BRACE_WORDS: usize = 1 << 16;
+
+/// The words of the brace sequence `start..end[..increment]`, with
+/// `start` and `end` either both integers or both letters. Integers
+/// are padded with zeros to the same width if either one has a leading
+/// zero. Return `None` if `text` is no sequence or a too long one.
+fn brace_sequence(text: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = text.split("..").collect();
+    let (start, end, increment) = match parts[..] {
+        [start, end] => (start, end, 1),
+        [start, end, increment] => (start, end, increment.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    let step = usize::try_from(increment.unsigned_abs()).ok()?.max(1);
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let is_padded = |s: &str| {
+            let digits = s.trim_start_matches(['-', '+']);
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if is_padded(start) || is_padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        if first.abs_diff(last) / step as u64 >= MAX_BRACE_WORDS as u64 {
+            return None;
+        }
+        let numbers: Vec<i64> = if first <= last {
+            (first..=last).step_by(step).collect()
+        } else {
+            (last..=first).rev().step_by(step).collect()
+        };
+        return Some(
+            numbers
+                .into_iter()
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        );
+    }
+
+    let letter = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(chr), None) if chr.is_ascii_alphabetic() => Some(chr as u8),
+            _ => None,
+        }
+    };
+    let (first, last) = (letter(start)?, letter(end)?);
+    let letters: Vec<u8> = if first <= last {
+        (first..=last).step_by(step).collect()
+    } else {
+        (last..=first).rev().step_by(step).collect()
+    };
+    Some(
+        letters
+            .into_iter()
+            .map(|b| char::from(b).to_string())
+            .collect(),
+    )
+}
+
+/// Perform brace expansion on `word`: `a{b,c}d` expands to `abd` and
+/// `acd`, `{1..3}` to `1`, `2` and `3`. Braces may be nested, those
+/// which are unbalanced or contain neither a comma nor a sequence are
+/// taken literally, so is a word expanding to more than
+/// `MAX_BRACE_WORDS` words.
+fn brace_expansion(word: &str) -> Vec<String> {
+    expand_braces(word).unwrap_or_else(|| vec![word.to_owned()])
+}
+
+/// Brace expansion of `word`, or `None` if it results in too many
+/// words.
+fn expand_braces(word: &str) -> Option<Vec<String>> {
+    let positions = brace_positions(word);
+
+    for (idx, &(open, chr)) in positions.iter().enumerate() {
+        if chr != '{' {
+            continue;
+        }
+
+        let mut depth = 0;
+        let mut bounds = vec![open];
+        let mut close = None;
+        for &(pos, chr) in &positions[idx + 1..] {
+            match chr {
+                '{' => depth += 1,
+                '}' if depth == 0 => {
+                    close = Some(pos);
+                    break;
+                }
+                '}' => depth -= 1,
+                ',' if depth == 0 => bounds.push(pos),
+                _ => (),
+            }
+        }
+        let close = match close {
+            Some(close) => close,
+            None => continue,
+        };
+
+        let items = if bounds.len() > 1 {
+            bounds.push(close);
+            let mut items = Vec::new();
+            for item in bounds.windows(2) {
+                items.append(&mut expand_braces(&word[item[0] + 1..item[1]])?);
+                if items.len() > MAX_BRACE_WORDS {
+                    return None;
+                }
+            }
+            items
+        } else {
+            match brace_sequence(&word[open + 1..close]) {
+                Some(items) => items,
+                None => continue,
+            }
+        };
+
+        let preamble = &word[..open];
+        let postscripts = expand_braces(&word[close + 1..])?;
+        if items.len().saturating_mul(postscripts.len()) > MAX_BRACE_WORDS {
+            return None;
+        }
+        return Some(
+            items
+                .iter()
+                .flat_map(|item| {
+                    postscripts
+                        .iter()
+                        .map(move |postscript| format!("{}{}{}", preamble, item, postscript))
+                })
+                .collect(),
+        );
+    }
+
+    Some(vec![word.to_owned()])
+}
+
+/// Perform parameter expansion and command substitution on `input`.
+pub(crate) fn expand(shell: &Shell, input: &str) -> Result<String, ExpansionError> {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(idx) = rest.find('$') {
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        match expand_dollar(shell, rest)? {
+            Some((expansion, len)) => {
+                result.push_str(&expansion);
+                rest = &rest[len..];
+            }
+            None => result.push('$'),
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// A word being expanded, or a part of it which `"$@"` made a field of
+/// its own
+struct Field {
+    /// The characters, each tagged with its origin
+    chars: Vec<(char, Origin)>,
+    /// Whether the field contains quotes, which keep it even if it is
+    /// empty
+    quoted: bool,
+}
+
+/// Check whether `input`, which starts right after a `$`, refers to all
+/// positional parameters, i.e. `@`, `*`, `{@}` or `{*}`. Return which
+/// one and the number of bytes consumed.
+fn all_positional(input: &str) -> Option<(char, usize)> {
+    match input.chars().next()? {
+        chr @ ('@' | '*') => Some((chr, 1)),
+        '{' if input.starts_with("{@}") => Some(('@', 3)),
+        '{' if input.starts_with("{*}") => Some(('*', 3)),
+        _ => None,
+    }
+}
+
+/// Perform tilde expansion, parameter expansion and command
+/// substitution outside of single quotes, followed by quote removal.
+/// The result is a single field, unless `$@` or `$*` split it.
+///
+/// Tilde prefixes are expanded at the start of the word and, if it is
+/// an assignment `name=value`, at the start of the value and after
+/// each `:` in it.
+fn expand_parts(shell: &Shell, word: &str) -> Result<Vec<Field>, ExpansionError> {
+    let mut fields = Vec::new();
+    let mut result = Vec::with_capacity(word.len());
+    let mut has_quotes = false;
+    let mut in_double_quotes = false;
+    // whether the current double quotes contain "$@" without any
+    // positional parameters, which does not count as quoted
+    let mut empty_at = false;
+    let mut rest = word;
+    let assignment = word.find('=').is_some_and(|idx| is_name(&word[..idx]));
+    let mut in_value = false;
+    let mut tilde_allowed = true;
+    let quoted = |in_double_quotes| {
+        if in_double_quotes {
+            Origin::Quoted
+        } else {
+            Origin::Literal
+        }
+    };
+
+    while let Some(chr) = rest.chars().next() {
+        rest = &rest[chr.len_utf8()..];
+        let at_tilde_position = std::mem::replace(&mut tilde_allowed, false);
+        match chr {
+            '~' if at_tilde_position && !in_double_quotes => {
+                let len = rest
+                    .find(|c| c == '/' || (in_value && c == ':'))
+                    .unwrap_or(rest.len());
+                let prefix = &rest[..len];
+                // quoted or expanded characters make the prefix literal
+                let expansion = if prefix.contains(['\\', '\'', '"', '$', '`']) {
+                    None
+                } else {
+                    tilde_expansion(shell, prefix)
+                };
+                match expansion {
+                    Some(dir) => {
+                        result.extend(dir.chars().map(|c| (c, Origin::Quoted)));
+                        rest = &rest[len..];
+                    }
+                    _ => result.push(('~', Origin::Literal)),
+                }
+            }
+            '=' if assignment && !in_value => {
+                result.push(('=', Origin::Literal));
+                in_value = true;
+                tilde_allowed = true;
+            }
+            ':' if in_value && !in_double_quotes => {
+                result.push((':', Origin::Literal));
+                tilde_allowed = true;
+            }
+            '\\' => match rest.chars().next() {
+                // within double quotes, a backslash only escapes
+                // characters which are special there
+                Some(escaped)
+                    if !in_double_quotes || matches!(escaped, '$' | '`' | '"' | '\\' | '\n') =>
+                {
+                    if escaped != '\n' {
+                        result.push((escaped, Origin::Quoted));
+                    }
+                    rest = &rest[escaped.len_utf8()..];
+                }
+                _ => result.push(('\\', quoted(in_double_quotes))),
+            },
+            '\'' if !in_double_quotes => {
+                has_quotes = true;
+                let len = rest.find('\'').unwrap_or(rest.len());
+                result.extend(rest[..len].chars().map(|c| (c, Origin::Quoted)));
+                rest = rest.get(len + 1..).unwrap_or_default();
+            }
+            '"' => {
+                in_double_quotes = !in_double_quotes;
+                if in_double_quotes {
+                    empty_at = false;
+                } else if !empty_at {
+                    has_quotes = true;
+                }
+            }
+            '$' if all_positional(rest).is_some() => {
+                let (chr, len) = all_positional(rest).unwrap();
+                rest = &rest[len..];
+                if chr == '*' && in_double_quotes {
+                    let joined = parameter(shell, "*").unwrap_or_default();
+                    result.extend(joined.chars().map(|c| (c, Origin::Quoted)));
+                    continue;
+                }
+
+                // each positional parameter is a field of its own
+                let origin = if in_double_quotes {
+                    Origin::Quoted
+                } else {
+                    Origin::Expansion
+                };
+                empty_at |= in_double_quotes && shell.positional.is_empty();
+                for (idx, param) in shell.positional.iter().enumerate() {
+                    if idx > 0 {
+                        fields.push(Field {
+                            chars: std::mem::take(&mut result),
+                            quoted: has_quotes || in_double_quotes,
+                        });
+                        has_quotes = in_double_quotes;
+                    }
+                    result.extend(param.chars().map(|c| (c, origin)));
+                }
+            }
+            '<' | '>' if !in_double_quotes && rest.starts_with('(') => {
+                match parser::substitution_len(&rest[1..]) {
+                    Some(len) => {
+                        let path = process_substitution(shell, &rest[1..=len], chr == '>');
+                        result.extend(path.chars().map(|c| (c, Origin::Quoted)));
+                        rest = &rest[len + 2..];
+                    }
+                    None => result.push((chr, Origin::Literal)),
+                }
+            }
+            '$' => match expand_dollar(shell, rest)? {
+                Some((expansion, len)) => {
+                    let origin = if in_double_quotes {
+                        Origin::Quoted
+                    } else {
+                        Origin::Expansion
+                    };
+                    result.extend(expansion.chars().map(|c| (c, origin)));
+                    rest = &rest[len..];
+                }
+                None => result.push(('$', quoted(in_double_quotes))),
+            },
+            _ => result.push((chr, quoted(in_double_quotes))),
+        }
+    }
+
+    fields.push(Field {
+        chars: result,
+        quoted: has_quotes,
+    });
+
+    Ok(fields)
+}
+
+/// Split `chars` into fields at the characters of `IFS` which result
+/// from unquoted expansions. Runs of `IFS` whitespace, with at most one
+/// other `IFS` character, form a single separator, whitespace at the
+/// start and end is dropped. Other `IFS` characters each end a field,
+/// which may be empty. An unset `IFS` splits at whitespace, an empty
+/// one does not split at all.
+fn field_splitting(shell: &Shell, chars: Vec<(char, Origin)>) -> Vec<Vec<(char, Origin)>> {
+    let ifs = shell.var("IFS").unwrap_or(" \t\n");
+    let is_separator =
+        |&(chr, origin): &(char, Origin)| origin == Origin::Expansion && ifs.contains(chr);
+    let is_whitespace = |chr: char| matches!(chr, ' ' | '\t' | '\n');
+
+    let mut fields = Vec::new();
+    let mut field = Vec::new();
+    let mut chars = chars.into_iter().peekable();
+    while let Some(item) = chars.next() {
+        if !is_separator(&item) {
+            field.push(item);
+            continue;
+        }
+
+        let mut delimits = !is_whitespace(item.0);
+        while let Some(next) = chars.peek() {
+            if !is_separator(next) || (delimits && !is_whitespace(next.0)) {
+                break;
+            }
+            delimits |= !is_whitespace(next.0);
+            chars.next();
+        }
+        if delimits || !field.is_empty() {
+            fields.push(std::mem::take(&mut field));
+        }
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+
+    fields
+}
+
+fn is_glob_char(chr: char) -> bool {
+    matches!(chr, '*' | '?' | '[')
+}
+
+/// Join `chars` into a string, escaping the quoted characters which are
+/// `special` by a backslash.
+fn escape_quoted(chars: &[(char, Origin)], special: &str) -> String {
+    let mut escaped = String::with_capacity(chars.len());
+    for &(chr, origin) in chars {
+        if origin == Origin::Quoted && special.contains(chr) {
+            escaped.push('\\');
+        }
+        escaped.push(chr);
+    }
+
+    escaped
+}
+
+/// Return the pathnames matching the pattern in `chars`, sorted, or
+/// `None` if there is no pattern or nothing matches. Quoted characters
+/// match themselves only.
+fn pathname_expansion(chars: &[(char, Origin)]) -> Option<Vec<String>> {
+    if !chars
+        .iter()
+        .any(|&(chr, origin)| origin != Origin::Quoted && is_glob_char(chr))
+    {
+        return None;
+    }
+
+    let pattern = CString::new(escape_quoted(chars, "*?[]\\")).ok()?;
+
+    let mut glob_buf = unsafe { MaybeUninit::<glob_t>::zeroed().assume_init() };
+    let paths = if unsafe { glob(pattern.as_ptr(), 0, None, &mut glob_buf) } == 0 {
+        let pathv = unsafe { slice::from_raw_parts(glob_buf.gl_pathv, glob_buf.gl_pathc) };
+        Some(
+            pathv
+                .iter()
+                .map(|path| {
+                    unsafe { CStr::from_ptr(*path) }
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect(),
+        )
+    } else {
+        None
+    };
+    unsafe { globfree(&mut glob_buf) };
+
+    paths
+}
+
+/// Expand `word` as written on the command line into fields: brace
+/// expansion, tilde expansion, parameter expansion, command
+/// substitution, field splitting, quote removal and, unless the noglob
+/// option is set, pathname expansion. A word expanding to nothing
+/// results in no field, unless it contains quotes.
+pub(crate) fn expand_word(shell: &Shell, word: &str) -> Result<Vec<String>, ExpansionError> {
+    let mut fields = Vec::new();
+    for word in brace_expansion(word) {
+        let mut split = Vec::new();
+        for field in expand_parts(shell, &word)? {
+            let mut parts = field_splitting(shell, field.chars);
+            if parts.is_empty() && field.quoted {
+                parts.push(Vec::new());
+            }
+            split.append(&mut parts);
+        }
+
+        for chars in split {
+            let paths = if shell.options.noglob {
+                None
+            } else {
+                pathname_expansion(&chars)
+            };
+            match paths {
+                Some(paths) => fields.extend(paths),
+                None => fields.push(chars.into_iter().map(|(chr, _)| chr).collect()),
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Expand `word` into a single string, in which the quoted characters
+/// that are `special` are escaped by a backslash, without field
+/// splitting and pathname expansion.
+fn expand_escaped(shell: &Shell, word: &str, special: &str) -> Result<String, ExpansionError> {
+    let fields: Vec<String> = expand_parts(shell, word)?
+        .into_iter()
+        .map(|field| escape_quoted(&field.chars, special))
+        .collect();
+
+    Ok(fields.join(" "))
+}
+
+/// Expand `word` like a word, but into a single string which is
+/// neither split into fields nor subject to pathname expansion.
+pub(crate) fn expand_string(shell: &Shell, word: &str) -> Result<String, ExpansionError> {
+    expand_escaped(shell, word, "")
+}
+
+/// Expand `word` into a pattern as understood by `fnmatch`, in which
+/// quoted characters match themselves only.
+pub(crate) fn expand_pattern(shell: &Shell, word: &str) -> Result<String, ExpansionError> {
+    expand_escaped(shell, word, "*?[]\\")
+}
+
+/// Expand `word` into an extended regular expression, in which quoted
+/// characters match themselves only.
+pub(crate) fn expand_regex(shell: &Shell, word: &str) -> Result<String, ExpansionError> {
+    expand_escaped(shell, word, "\\.[]()*+?{}|^$")
+}
+
+/// Expand the assignment `word`, `name=value`, into the name and the
+/// value. The value is expanded like `expand_string` does.
+pub(crate) fn expand_assignment(
+    shell: &Shell,
+    word: &str,
+) -> Result<(String, String), ExpansionError> {
+    let name = word.split('=').next().unwrap_or_default();
+    let expanded = expand_string(shell, word)?;
+
+    Ok((name.to_owned(), expanded[name.len() + 1..].to_owned()))
+}
+
+/// Quote `s` such that the shell reads it back as a single word.
+pub(crate) fn quote(s: &str) -> String {
+    let is_safe = |chr: char| {
+        chr.is_ascii_alphanumeric()
+            || matches!(
+                chr,
+                '/' | '.' | '_' | '-' | '+' | ':' | ',' | '=' | '@' | '%'
+            )
+    };
+
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return s.to_owned();
+    }
+
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::fs;
+
+    use super::ExpansionError;
+    use crate::shell::Shell;
+    use crate::testutil::TempDir;
+
+    fn expand_dollar(shell: &Shell, input: &str) -> Option<(String, usize)> {
+        super::expand_dollar(shell, input).unwrap()
+    }
+
+    #[test]
+    fn test_expand_parameters() {
+        let mut shell = Shell::default();
+        shell.set_var("FOO", "foo");
+        shell.last_status = 3;
+
+        assert_eq!(
+            expand_dollar(&shell, "FOO.txt"),
+            Some(("foo".to_owned(), 3))
+        );
+        assert_eq!(
+            expand_dollar(&shell, "{FOO}bar"),
+            Some(("foo".to_owned(), 5))
+        );
+        assert_eq!(expand_dollar(&shell, "BAR"), Some(("".to_owned(), 3)));
+        assert_eq!(expand_dollar(&shell, "?"), Some(("3".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, "!"), Some(("".to_owned(), 1)));
+        shell.last_background_pid = Some(42);
+        assert_eq!(expand_dollar(&shell, "!"), Some(("42".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, " "), None);
+        assert_eq!(expand_dollar(&shell, "{FOO"), None);
+
+        shell.positional = vec!["a".to_owned(), "b".to_owned()];
+        assert_eq!(expand_dollar(&shell, "1"), Some(("a".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, "23"), Some(("b".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, "#"), Some(("2".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, "{10}"), Some(("".to_owned(), 4)));
+
+        shell.options.nounset = true;
+        shell.options.xtrace = true;
+        assert_eq!(expand_dollar(&shell, "-"), Some(("ux".to_owned(), 1)));
+        assert_eq!(expand_dollar(&shell, "FOO"), Some(("foo".to_owned(), 3)));
+        assert_eq!(
+            super::expand_dollar(&shell, "BAR"),
+            Err(ExpansionError::Unbound("BAR".to_owned()))
+        );
+        assert_eq!(
+            super::expand_dollar(&shell, "{3}"),
+            Err(ExpansionError::Unbound("3".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut shell = Shell::default();
+        shell.set_var("HOME", "/home/user");
+
+        assert_eq!(super::expand(&shell, "abc"), Ok("abc".to_owned()));
+        assert_eq!(
+            super::expand(&shell, "$HOME/.env"),
+            Ok("/home/user/.env".to_owned())
+        );
+        assert_eq!(
+            super::expand(&shell, "a$ ${HOME"),
+            Ok("a$ ${HOME".to_owned())
+        );
+    }
+
+    fn expand_word(shell: &Shell, word: &str) -> Vec<String> {
+        super::expand_word(shell, word).unwrap()
+    }
+
+    #[test]
+    fn test_expand_word() {
+        let mut shell = Shell::default();
+        shell.set_var("FOO", "foo bar");
+
+        assert_eq!(expand_word(&shell, "abc"), vec!["abc"]);
+        assert_eq!(expand_word(&shell, "$FOO"), vec!["foo", "bar"]);
+        assert_eq!(expand_word(&shell, "'$FOO'"), vec!["$FOO"]);
+        assert_eq!(expand_word(&shell, "\"$FOO\""), vec!["foo bar"]);
+        assert_eq!(expand_word(&shell, "\"'$FOO'\""), vec!["'foo bar'"]);
+        assert_eq!(expand_word(&shell, "a\\$FOO"), vec!["a$FOO"]);
+        assert_eq!(expand_word(&shell, "\"a\\$\\b\""), vec!["a$\\b"]);
+        assert_eq!(expand_word(&shell, "a\\\"bc"), vec!["a\"bc"]);
+        assert_eq!(expand_word(&shell, "x'y z'\"\""), vec!["xy z"]);
+    }
+
+    #[test]
+    fn test_field_splitting() {
+        let mut shell = Shell::default();
+        shell.set_var("A", "  a  b\tc\n ");
+        shell.set_var("B", "a::b: c :");
+        shell.set_var("E", "");
+
+        assert_eq!(expand_word(&shell, "$A"), vec!["a", "b", "c"]);
+        assert_eq!(expand_word(&shell, "x${A}y"), vec!["x", "a", "b", "c", "y"]);
+        assert_eq!(expand_word(&shell, "\"$A\""), vec!["  a  b\tc\n "]);
+        assert_eq!(expand_word(&shell, "$B"), vec!["a::b:", "c", ":"]);
+        assert_eq!(expand_word(&shell, "$E"), Vec::<String>::new());
+        assert_eq!(expand_word(&shell, "\"$E\""), vec![""]);
+        assert_eq!(expand_word(&shell, "''$E"), vec![""]);
+        assert_eq!(expand_word(&shell, "a' 'b"), vec!["a b"]);
+
+        shell.set_var("IFS", ": ");
+        assert_eq!(expand_word(&shell, "$B"), vec!["a", "", "b", "c"]);
+        assert_eq!(expand_word(&shell, "$A"), vec!["a", "b\tc\n"]);
+        shell.set_var("B", ":a : :b");
+        assert_eq!(expand_word(&shell, "$B"), vec!["", "a", "", "b"]);
+
+        shell.set_var("IFS", "");
+        assert_eq!(expand_word(&shell, "$A"), vec!["  a  b\tc\n "]);
+    }
+
+    #[test]
+    fn test_positional_parameters() {
+        let mut shell = Shell::default();
+        shell.name = "stsh".to_owned();
+        shell.pid = 42;
+        shell.positional = vec!["a b".to_owned(), "".to_owned(), "c".to_owned()];
+
+        assert_eq!(expand_word(&shell, "$0"), vec!["stsh"]);
+        assert_eq!(expand_word(&shell, "$$"), vec!["42"]);
+        assert_eq!(expand_word(&shell, "\"$@\""), vec!["a b", "", "c"]);
+        assert_eq!(expand_word(&shell, "x\"$@\"y"), vec!["xa b", "", "cy"]);
+        assert_eq!(expand_word(&shell, "\"${@}\""), vec!["a b", "", "c"]);
+        assert_eq!(expand_word(&shell, "$@"), vec!["a", "b", "c"]);
+        assert_eq!(expand_word(&shell, "$*"), vec!["a", "b", "c"]);
+        assert_eq!(expand_word(&shell, "\"$*\""), vec!["a b  c"]);
+        shell.set_var("IFS", ",");
+        assert_eq!(expand_word(&shell, "\"$*\""), vec!["a b,,c"]);
+        assert_eq!(expand_word(&shell, "$*"), vec!["a b", "c"]);
+        shell.set_var("IFS", "");
+        assert_eq!(expand_word(&shell, "\"$*\""), vec!["a bc"]);
+        assert_eq!(expand_word(&shell, "$@"), vec!["a b", "c"]);
+
+        shell.positional.clear();
+        assert_eq!(expand_word(&shell, "\"$@\""), Vec::<String>::new());
+        assert_eq!(expand_word(&shell, "\"$*\""), vec![""]);
+        assert_eq!(expand_word(&shell, "x\"$@\""), vec!["x"]);
+        assert_eq!(expand_word(&shell, "\"\"\"$@\""), vec![""]);
+    }
+
+    #[test]
+    fn test_expand_assignment() {
+        let mut shell = Shell::default();
+        shell.set_var("HOME", "/home/user");
+        shell.set_var("A", "a  *");
+        shell.positional = vec!["x".to_owned(), "y".to_owned()];
+
+        let expand = |word| super::expand_assignment(&shell, word).unwrap();
+        assert_eq!(expand("X="), ("X".to_owned(), "".to_owned()));
+        assert_eq!(expand("X=$A"), ("X".to_owned(), "a  *".to_owned()));
+        assert_eq!(
+            expand("X=~:~/b"),
+            ("X".to_owned(), "/home/user:/home/user/b".to_owned())
+        );
+        assert_eq!(
+            expand("X='a=b'\"$@\""),
+            ("X".to_owned(), "a=bx y".to_owned())
+        );
+        assert_eq!(expand("X={a,b}"), ("X".to_owned(), "{a,b}".to_owned()));
+    }
+
+    #[test]
+    fn test_expand_pattern() {
+        let mut shell = Shell::default();
+        shell.set_var("A", "a  *");
+
+        assert_eq!(super::expand_string(&shell, "$A'?'").unwrap(), "a  *?");
+        assert_eq!(super::expand_pattern(&shell, "$A'?'").unwrap(), "a  *\\?");
+        assert_eq!(super::expand_pattern(&shell, "\"$A\"*").unwrap(), "a  \\**");
+        assert_eq!(
+            super::expand_regex(&shell, "^x'.(*'$").unwrap(),
+            "^x\\.\\(\\*$"
+        );
+    }
+
+    #[test]
+    fn test_tilde_expansion() {
+        let mut shell = Shell::default();
+        shell.set_var("HOME", "/home/user");
+        shell.set_var("PWD", "/tmp");
+        // root's home directory is not /root everywhere
+        let root_home = unsafe {
+            let pwd = libc::getpwnam(b"root\0".as_ptr() as *const libc::c_char);
+            assert!(!pwd.is_null());
+            CStr::from_ptr((*pwd).pw_dir).to_str().unwrap().to_owned()
+        };
+
+        assert_eq!(expand_word(&shell, "~"), vec!["/home/user"]);
+        assert_eq!(expand_word(&shell, "~/a b"), vec!["/home/user/a b"]);
+        assert_eq!(expand_word(&shell, "~+/x"), vec!["/tmp/x"]);
+        assert_eq!(expand_word(&shell, "~-"), vec!["~-"]);
+        assert_eq!(
+            expand_word(&shell, "~root/bin"),
+            vec![format!("{}/bin", root_home)]
+        );
+        assert_eq!(expand_word(&shell, "~no-such-user"), vec!["~no-such-user"]);
+        assert_eq!(expand_word(&shell, "\"~\""), vec!["~"]);
+        assert_eq!(expand_word(&shell, "'~'/x"), vec!["~/x"]);
+        assert_eq!(expand_word(&shell, "\\~"), vec!["~"]);
+        assert_eq!(expand_word(&shell, "~'root'"), vec!["~root"]);
+        assert_eq!(expand_word(&shell, "a~"), vec!["a~"]);
+        assert_eq!(expand_word(&shell, "a:~"), vec!["a:~"]);
+        assert_eq!(
+            expand_word(&shell, "PATH=~/bin:~root:/bin"),
+            vec![format!("PATH=/home/user/bin:{}:/bin", root_home)]
+        );
+        assert_eq!(expand_word(&shell, "PATH=a=~"), vec!["PATH=a=~"]);
+        assert_eq!(expand_word(&shell, "-x=~"), vec!["-x=~"]);
+
+        shell.set_var("HOME", "/home/a*");
+        assert_eq!(expand_word(&shell, "~"), vec!["/home/a*"]);
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        let expand = |word| super::brace_expansion(word);
+
+        assert_eq!(expand("a{b,c}d"), vec!["abd", "acd"]);
+        assert_eq!(expand("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("x{a,{b,c}y}"), vec!["xa", "xby", "xcy"]);
+        assert_eq!(expand("x{,s}"), vec!["x", "xs"]);
+        assert_eq!(expand("{1..4}"), vec!["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..-1..2}"), vec!["3", "1", "-1"]);
+        assert_eq!(expand("{1..10..4}"), vec!["1", "5", "9"]);
+        assert_eq!(expand("{08..11}"), vec!["08", "09", "10", "11"]);
+        assert_eq!(expand("{-2..02}"), vec!["-2", "-1", "00", "01", "02"]);
+        assert_eq!(expand("{a..e..2}"), vec!["a", "c", "e"]);
+        assert_eq!(expand("{c..a}"), vec!["c", "b", "a"]);
+        assert_eq!(expand("{a}{b,c}}"), vec!["{a}b}", "{a}c}"]);
+        assert_eq!(expand("{a{b,c}"), vec!["{ab", "{ac"]);
+        assert_eq!(expand("{a,b"), vec!["{a,b"]);
+        assert_eq!(expand("{1..x}"), vec!["{1..x}"]);
+        assert_eq!(expand("{1..65536}").len(), 65536);
+        assert_eq!(expand("{0..65536}"), vec!["{0..65536}"]);
+        assert_eq!(expand("{1..256}{1..256}").len(), 65536);
+        assert_eq!(expand("{1..60000}{1..60000}"), vec!["{1..60000}{1..60000}"]);
+        let nested = "{a,b}".repeat(17);
+        assert_eq!(expand(&nested), vec![nested.clone()]);
+        let nested = format!("{{x,{}}}", nested);
+        assert_eq!(expand(&nested), vec![nested.clone()]);
+        assert_eq!(
+            expand("a{1..9223372036854775807..2}"),
+            vec!["a{1..9223372036854775807..2}"]
+        );
+        assert_eq!(expand("{}"), vec!["{}"]);
+        assert_eq!(expand("'{a,b}'"), vec!["'{a,b}'"]);
+        assert_eq!(expand("\\{a,b}"), vec!["\\{a,b}"]);
+        assert_eq!(expand("{'a,b',c}"), vec!["'a,b'", "c"]);
+        assert_eq!(expand("${A},{x}"), vec!["${A},{x}"]);
+        assert_eq!(expand("{$(echo a,b),c}"), vec!["$(echo a,b)", "c"]);
+
+        let mut shell = Shell::default();
+        shell.set_var("A", "1,2");
+        assert_eq!(expand_word(&shell, "{$A,\"b c\"}"), vec!["1,2", "b c"]);
+    }
+
+    #[test]
+    fn test_pathname_expansion() {
+        let temp_dir = TempDir::new("glob");
+        for name in ["a1", "a2", "b", "a*"] {
+            fs::write(temp_dir.join(name), "").unwrap();
+        }
+        let dir = temp_dir.to_string_lossy().into_owned();
+
+        let mut shell = Shell::default();
+        shell.set_var("DIR", &dir);
+        shell.set_var("PATTERN", "a?");
+
+        assert_eq!(
+            expand_word(&shell, "$DIR/a?"),
+            vec![
+                format!("{}/a*", dir),
+                format!("{}/a1", dir),
+                format!("{}/a2", dir)
+            ]
+        );
+        assert_eq!(
+            expand_word(&shell, "$DIR/a[0-9]"),
+            vec![format!("{}/a1", dir), format!("{}/a2", dir)]
+        );
+        assert_eq!(
+            expand_word(&shell, "$DIR/$PATTERN"),
+            expand_word(&shell, "$DIR/a?")
+        );
+        assert_eq!(
+            expand_word(&shell, "$DIR/'a*'"),
+            vec![format!("{}/a*", dir)]
+        );
+        assert_eq!(
+            expand_word(&shell, "\"$DIR/$PATTERN\""),
+            vec![format!("{}/a?", dir)]
+        );
+        assert_eq!(expand_word(&shell, "$DIR/c*"), vec![format!("{}/c*", dir)]);
+
+        shell.options.noglob = true;
+        assert_eq!(expand_word(&shell, "$DIR/a?"), vec![format!("{}/a?", dir)]);
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(super::quote("abc-1"), "abc-1");
+        assert_eq!(super::quote(""), "''");
+        assert_eq!(super::quote("a b"), "'a b'");
+        assert_eq!(super::quote("it's"), "'it'\\''s'");
+    }
+}
@@ -0,0 +1,178 @@
+//! Lookup of commands in `PATH`, remembering previous lookups
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use libc::{access, X_OK};
+
+use crate::shell::Shell;
+
+/// Search path used if `PATH` is not set
+const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct HashedCommand {
+    pub path: PathBuf,
+    /// How often the command was looked up
+    pub hits: usize,
+}
+
+/// Commands which were found in `PATH` before, by name.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CommandHash {
+    commands: BTreeMap<String, HashedCommand>,
+}
+
+impl CommandHash {
+    pub(crate) fn get(&self, name: &str) -> Option<&HashedCommand> {
+        self.commands.get(name)
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, path: PathBuf) {
+        self.commands
+            .insert(name.to_owned(), HashedCommand { path, hits: 0 });
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        self.commands.remove(name).is_some()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &HashedCommand)> {
+        self.commands.iter()
+    }
+}
+
+/// Whether `path` is a regular file the shell may execute.
+pub(crate) fn is_executable(path: &Path) -> bool {
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(_) => return false,
+    };
+
+    path.is_file() && unsafe { access(c_path.as_ptr(), X_OK) } == 0
+}
+
+/// The paths `name` would have in each directory of the search path
+/// `path`, in order. An empty directory stands for the current one.
+fn path_candidates<'a>(path: &'a str, name: &'a str) -> impl Iterator<Item = PathBuf> + 'a {
+    env::split_paths(path).map(move |dir| {
+        if dir.as_os_str().is_empty() {
+            PathBuf::from(".").join(name)
+        } else {
+            dir.join(name)
+        }
+    })
+}
+
+/// All executables called `name` in the directories of `PATH`, in
+/// order.
+pub(crate) fn search_path(shell: &Shell, name: &str) -> Vec<PathBuf> {
+    let path = shell.var("PATH").unwrap_or(DEFAULT_PATH);
+    path_candidates(path, name)
+        .filter(|candidate| is_executable(candidate))
+        .collect()
+}
+
+/// Find the executable for command `name` in the search path `path`,
+/// without using the remembered commands. Names containing a slash are
+/// taken as they are. If there is only a file of that name which is not
+/// executable, return it, such that executing it fails with "Permission
+/// denied".
+pub(crate) fn search_command(path: &str, name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    let mut fallback = None;
+    for candidate in path_candidates(path, name) {
+        if is_executable(&candidate) {
+            return Some(candidate);
+        }
+        if fallback.is_none() && candidate.is_file() {
+            fallback = Some(candidate);
+        }
+    }
+
+    fallback
+}
+
+/// Find the executable for command `name` like `search_command` does
+/// in `PATH`, unless it was found before. Executables found are
+/// remembered. Return `None` if there is no such command.
+pub(crate) fn find_command(shell: &mut Shell, name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    if let Some(hashed) = shell.hash.commands.get_mut(name) {
+        // search again if it was removed in the meantime
+        if is_executable(&hashed.path) {
+            hashed.hits += 1;
+            return Some(hashed.path.clone());
+        }
+    }
+
+    let path = search_command(shell.var("PATH").unwrap_or(DEFAULT_PATH), name)?;
+    if !is_executable(&path) {
+        return Some(path);
+    }
+    shell.hash.commands.insert(
+        name.to_owned(),
+        HashedCommand {
+            path: path.clone(),
+            hits: 1,
+        },
+    );
+
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::shell::Shell;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn test_find_command() {
+        let dir = TempDir::new("hash");
+        let cmd = dir.join("cmd");
+        fs::write(&cmd, "").unwrap();
+        fs::set_permissions(&cmd, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("data"), "").unwrap();
+
+        let mut shell = Shell::default();
+        shell.set_var("PATH", &format!("/nonexistent:{}", dir.display()));
+
+        assert_eq!(super::find_command(&mut shell, "cmd"), Some(cmd.clone()));
+        assert_eq!(super::find_command(&mut shell, "cmd"), Some(cmd.clone()));
+        assert_eq!(shell.hash.get("cmd").unwrap().hits, 2);
+        assert_eq!(
+            super::find_command(&mut shell, "data"),
+            Some(dir.join("data"))
+        );
+        assert!(shell.hash.get("data").is_none());
+        assert_eq!(super::find_command(&mut shell, "nosuch"), None);
+        assert_eq!(
+            super::find_command(&mut shell, "./nosuch"),
+            Some("./nosuch".into())
+        );
+
+        shell.set_var("PATH", "/nonexistent");
+        assert!(shell.hash.is_empty());
+        assert_eq!(super::find_command(&mut shell, "cmd"), None);
+    }
+}
@@ -0,0 +1,669 @@
+//! Job table of background pipelines
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::mem;
+use std::panic;
+use std::thread::{self, JoinHandle};
+
+use libc::{
+    __errno_location, c_int, c_void, close, kill, pid_t, pipe2, poll, pollfd, strsignal, waitpid,
+    write, ECHILD, EINTR, O_CLOEXEC, POLLIN, SIGCHLD, SIGCONT, SIGHUP, WCONTINUED, WEXITSTATUS,
+    WIFCONTINUED, WIFSIGNALED, WIFSTOPPED, WNOHANG, WSTOPSIG, WTERMSIG, WUNTRACED,
+};
+
+use crate::execution::exit_status;
+use crate::shell::Shell;
+use crate::sig::{
+    mask_sigchld, read_signals, suspend_for_signal, take_trapped_signal, unmask_sigchld, SigError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum JobState {
+    Running,
+    /// Stopped by the given signal
+    Stopped(c_int),
+    /// The last process exited with the given status
+    Exited(c_int),
+    /// The last process was killed by the given signal
+    Signaled(c_int),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Job {
+    /// The job number, as in `%1`
+    pub id: usize,
+    pub pgid: pid_t,
+    /// The processes of the pipeline, with their status as reported
+    /// by `waitpid` once they terminated
+    pub processes: Vec<(pid_t, Option<c_int>)>,
+    pub state: JobState,
+    /// The command line of the pipeline, for reporting
+    pub command: String,
+    /// Not to be sent `SIGHUP` when the shell exits, see `disown -h`
+    pub nohup: bool,
+}
+
+impl Job {
+    /// Reap the processes which terminated and notice the ones which
+    /// were stopped or continued. With `options` 0, block until all of
+    /// them terminated or one of them stopped, with `WNOHANG`, do not
+    /// block. A job which is stopped already is not waited for.
+    ///
+    /// Blocking ends early when a signal a trap is set for is caught,
+    /// whose number is returned as the error.
+    fn update(&mut self, options: c_int) -> Result<(), c_int> {
+        let blocking = options & WNOHANG == 0;
+        if blocking {
+            self.update(WNOHANG)?;
+            if let JobState::Stopped(_) = self.state {
+                return Ok(());
+            }
+        }
+        let options = if blocking {
+            options | WUNTRACED
+        } else {
+            options | WUNTRACED | WCONTINUED
+        };
+
+        for (pid, status) in self.processes.iter_mut().filter(|(_, s)| s.is_none()) {
+            let mut wstatus = 0;
+            loop {
+                match unsafe { waitpid(*pid, &mut wstatus, options) } {
+                    0 => (),
+                    -1 => match unsafe { *__errno_location() } {
+                        EINTR => match take_trapped_signal() {
+                            Some(signum) => return Err(signum),
+                            None => continue,
+                        },
+                        // reaped elsewhere, the status is lost
+                        ECHILD => *status = Some(0),
+                        _ => (),
+                    },
+                    _ if WIFSTOPPED(wstatus) => self.state = JobState::Stopped(WSTOPSIG(wstatus)),
+                    _ if WIFCONTINUED(wstatus) => self.state = JobState::Running,
+                    _ => *status = Some(wstatus),
+                }
+                break;
+            }
+            if blocking && matches!(self.state, JobState::Stopped(_)) {
+                return Ok(());
+            }
+        }
+        self.update_state();
+
+        Ok(())
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        matches!(self.state, JobState::Exited(_) | JobState::Signaled(_))
+    }
+
+    fn update_state(&mut self) {
+        if self.processes.iter().all(|(_, status)| status.is_some()) {
+            let wstatus = self
+                .processes
+                .last()
+                .and_then(|(_, status)| *status)
+                .unwrap_or(0);
+            self.state = if WIFSIGNALED(wstatus) {
+                JobState::Signaled(WTERMSIG(wstatus))
+            } else {
+                JobState::Exited(WEXITSTATUS(wstatus))
+            };
+        }
+    }
+
+    /// The exit status as seen by `$?`. A stopped job reports the
+    /// signal which stopped it like a killed one.
+    pub(crate) fn status(&self) -> c_int {
+        match self.state {
+            JobState::Running => 0,
+            JobState::Exited(status) => status,
+            JobState::Stopped(signum) | JobState::Signaled(signum) => 128 + signum,
+        }
+    }
+
+    /// The exit status of process `pid` of this job, if it
+    /// terminated.
+    pub(crate) fn process_status(&self, pid: pid_t) -> Option<c_int> {
+        self.processes
+            .iter()
+            .find(|(p, _)| *p == pid)
+            .and_then(|(_, status)| status.map(exit_status))
+    }
+
+    /// The state as shown to the user, e.g. `Done`, `Exit 1` or
+    /// `Killed`.
+    fn describe_state(&self) -> String {
+        match self.state {
+            JobState::Running => "Running".to_owned(),
+            JobState::Stopped(_) => "Stopped".to_owned(),
+            JobState::Exited(0) => "Done".to_owned(),
+            JobState::Exited(status) => format!("Exit {}", status),
+            JobState::Signaled(signum) => unsafe { CStr::from_ptr(strsignal(signum)) }
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+
+    /// The line reporting the job, e.g. `[1]+  Done   sleep 1`, where
+    /// `marker` tells whether it is the current (`+`) or the previous
+    /// (`-`) job.
+    pub(crate) fn format(&self, marker: char) -> String {
+        format!(
+            "[{}]{}  {:<24}{}",
+            self.id,
+            marker,
+            self.describe_state(),
+            self.command
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Jobs {
+    /// in the order they were started
+    jobs: Vec<Job>,
+    /// Exit statuses of the processes of jobs which were reported as
+    /// finished and removed, by process id, until `wait` asks for them
+    reaped: HashMap<pid_t, c_int>,
+}
+
+impl Jobs {
+    /// Add a job for the processes `pids` in process group `pgid` and
+    /// return its number, which is one more than the highest number in
+    /// use.
+    pub(crate) fn add(&mut self, pgid: pid_t, pids: &[pid_t], command: String) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        self.jobs.push(Job {
+            id,
+            pgid,
+            processes: pids.iter().map(|pid| (*pid, None)).collect(),
+            state: JobState::Running,
+            command,
+            nohup: false,
+        });
+
+        id
+    }
+
+    /// Add a job for the processes `pids` of a foreground pipeline
+    /// which was stopped by `signum`, making it the current job, and
+    /// return the line reporting it.
+    pub(crate) fn add_stopped(
+        &mut self,
+        pgid: pid_t,
+        pids: &[pid_t],
+        command: String,
+        signum: c_int,
+    ) -> String {
+        self.add(pgid, pids, command);
+        let idx = self.jobs.len() - 1;
+        self.jobs[idx].state = JobState::Stopped(signum);
+
+        self.jobs[idx].format(self.marker(idx))
+    }
+
+    /// Number of jobs which did not finish yet.
+    pub(crate) fn running(&self) -> usize {
+        self.jobs.iter().filter(|job| !job.is_finished()).count()
+    }
+
+    pub(crate) fn has_stopped(&self) -> bool {
+        self.jobs
+            .iter()
+            .any(|job| matches!(job.state, JobState::Stopped(_)))
+    }
+
+    /// Reap the processes of all jobs which terminated, without
+    /// blocking.
+    pub(crate) fn update(&mut self) {
+        for job in self.jobs.iter_mut().filter(|job| !job.is_finished()) {
+            // never interrupted without blocking
+            let _ = job.update(WNOHANG);
+        }
+    }
+
+    /// Look up a job by a job spec: `%n` is job number n, `%+` or `%%`
+    /// the current job and `%-` the previous one, `%string` the job
+    /// whose command starts with string and `%?string` the one whose
+    /// command contains it. Return its index.
+    pub(crate) fn find(&self, spec: &str) -> Option<usize> {
+        let spec = spec.strip_prefix('%')?;
+        let matching = |matches: &dyn Fn(&Job) -> bool| {
+            let mut indices = self.jobs.iter().enumerate().filter(|(_, job)| matches(job));
+            match (indices.next(), indices.next()) {
+                (Some((idx, _)), None) => Some(idx),
+                // ambiguous
+                _ => None,
+            }
+        };
+
+        match spec {
+            "" | "+" | "%" => self.jobs.len().checked_sub(1),
+            "-" => self.jobs.len().checked_sub(2),
+            _ if spec.chars().all(|chr| chr.is_ascii_digit()) => {
+                let id = spec.parse::<usize>().ok()?;
+                self.jobs.iter().position(|job| job.id == id)
+            }
+            _ => match spec.strip_prefix('?') {
+                Some(string) => matching(&|job| job.command.contains(string)),
+                None => matching(&|job| job.command.starts_with(spec)),
+            },
+        }
+    }
+
+    pub(crate) fn get(&self, idx: usize) -> &Job {
+        &self.jobs[idx]
+    }
+
+    pub(crate) fn get_mut(&mut self, idx: usize) -> &mut Job {
+        &mut self.jobs[idx]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// Remove the job at `idx` from the table, without waiting for it.
+    pub(crate) fn remove(&mut self, idx: usize) -> Job {
+        self.jobs.remove(idx)
+    }
+
+    /// Send `SIGHUP` to all jobs which did not finish, except the ones
+    /// marked by `disown -h`. Stopped jobs are continued as well, such
+    /// that they can handle the signal.
+    pub(crate) fn hang_up(&mut self) {
+        self.update();
+        for job in self
+            .jobs
+            .iter()
+            .filter(|job| !job.is_finished() && !job.nohup)
+        {
+            unsafe { kill(-job.pgid, SIGHUP) };
+            if matches!(job.state, JobState::Stopped(_)) {
+                unsafe { kill(-job.pgid, SIGCONT) };
+            }
+        }
+    }
+
+    /// The index of the job process `pid` belongs to.
+    pub(crate) fn find_pid(&self, pid: pid_t) -> Option<usize> {
+        self.jobs
+            .iter()
+            .position(|job| job.processes.iter().any(|(p, _)| *p == pid))
+    }
+
+    /// Wait until the job at `idx` finished and remove it. If it is
+    /// stopped instead, it stays in the table and a copy is returned.
+    ///
+    /// Like all waits for jobs, it is interrupted by a signal a trap is
+    /// set for, whose number is returned as the error. Signals caught
+    /// before the last call of `take_trapped_signal` do not count.
+    pub(crate) fn wait(&mut self, idx: usize) -> Result<Job, c_int> {
+        self.jobs[idx].update(0)?;
+        if self.jobs[idx].is_finished() {
+            Ok(self.jobs.remove(idx))
+        } else {
+            Ok(self.jobs[idx].clone())
+        }
+    }
+
+    /// Take the exit status of process `pid` of a job which was removed
+    /// after it was reported as finished.
+    pub(crate) fn take_reaped(&mut self, pid: pid_t) -> Option<c_int> {
+        self.reaped.remove(&pid)
+    }
+
+    /// Wait for all jobs and remove them, except the stopped ones. The
+    /// statuses of processes reaped before are forgotten.
+    pub(crate) fn wait_all(&mut self) -> Result<(), c_int> {
+        self.reaped.clear();
+        let mut idx = 0;
+        while idx < self.jobs.len() {
+            if !self.wait(idx)?.is_finished() {
+                idx += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait until any job finished and remove it, or return `None` if
+    /// there are no jobs which may finish. A job which already finished
+    /// is returned right away. Trapped signals interrupt it like `wait`.
+    ///
+    /// Only the processes of jobs are reaped, others like those of
+    /// process substitutions are left alone. Waiting relies on the
+    /// handler the shell installs for `SIGCHLD`.
+    pub(crate) fn wait_any(&mut self) -> Result<Option<Job>, c_int> {
+        // blocked while looking at the jobs, such that a process which
+        // terminates in the meantime ends the suspension right away
+        let chld_set = match mask_sigchld() {
+            Ok(chld_set) => chld_set,
+            Err(_) => return Ok(None),
+        };
+        let job = loop {
+            self.update();
+            if let Some(idx) = self.jobs.iter().position(|job| job.is_finished()) {
+                break Ok(Some(self.jobs.remove(idx)));
+            }
+            if self
+                .jobs
+                .iter()
+                .all(|job| matches!(job.state, JobState::Stopped(_)))
+            {
+                break Ok(None);
+            }
+            if let Some(signum) = take_trapped_signal() {
+                break Err(signum);
+            }
+
+            suspend_for_signal();
+        };
+        let _ = unmask_sigchld(chld_set);
+
+        job
+    }
+
+    /// The marker of the job at `idx`: the most recently started job is
+    /// the current one, the one before the previous one.
+    fn marker(&self, idx: usize) -> char {
+        match self.jobs.len() - idx {
+            1 => '+',
+            2 => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Remove the finished jobs from the table and return the lines
+    /// reporting them. The exit statuses of their processes are kept
+    /// for `wait`.
+    pub(crate) fn take_notifications(&mut self) -> Vec<String> {
+        let notifications = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.is_finished())
+            .map(|(idx, job)| job.format(self.marker(idx)))
+            .collect();
+        for job in self.jobs.iter().filter(|job| job.is_finished()) {
+            for &(pid, _) in &job.processes {
+                if let Some(status) = job.process_status(pid) {
+                    self.reaped.insert(pid, status);
+                }
+            }
+        }
+        self.jobs.retain(|job| !job.is_finished());
+
+        notifications
+    }
+}
+
+/// Report the background jobs which finished on standard error.
+/// Only interactive shells do so, non-interactive ones keep the jobs.
+pub(crate) fn report_finished_jobs(shell: &mut Shell) {
+    if !shell.interactive {
+        return;
+    }
+
+    for notification in shell.jobs.take_notifications() {
+        eprintln!("{}", notification);
+    }
+}
+
+/// Reports jobs right when they finish while the shell waits for
+/// input, as the notify option demands. A thread of its own watches
+/// the self-pipe and owns the job table meanwhile.
+pub(crate) struct JobWatcher {
+    thread: JoinHandle<(Jobs, Vec<c_int>)>,
+    /// written to stop the thread
+    stop_fd: c_int,
+}
+
+impl JobWatcher {
+    /// Take over `jobs` and update them whenever signals can be read
+    /// from `signal_fd`, passing the lines reporting finished jobs to
+    /// `report`.
+    pub(crate) fn start(
+        jobs: &mut Jobs,
+        signal_fd: c_int,
+        mut report: impl FnMut(String) + Send + 'static,
+    ) -> Result<Self, SigError> {
+        let mut filedes: [c_int; 2] = [-1, -1];
+        if unsafe { pipe2(filedes.as_mut_ptr(), O_CLOEXEC) } == -1 {
+            return Err(SigError::Syscall(unsafe { *__errno_location() }));
+        }
+        let [stop_read_fd, stop_fd] = filedes;
+
+        let mut jobs = mem::take(jobs);
+        let thread = thread::spawn(move || {
+            let mut signals = Vec::new();
+            loop {
+                let mut fds = [signal_fd, stop_read_fd].map(|fd| pollfd {
+                    fd,
+                    events: POLLIN,
+                    revents: 0,
+                });
+                if unsafe { poll(fds.as_mut_ptr(), fds.len() as _, -1) } == -1 {
+                    match unsafe { *__errno_location() } {
+                        EINTR => continue,
+                        _ => break,
+                    }
+                }
+                if fds[1].revents != 0 {
+                    break;
+                }
+
+                let taken = match read_signals(signal_fd) {
+                    Ok(taken) => taken,
+                    Err(_) => break,
+                };
+                if taken.contains(&SIGCHLD) {
+                    jobs.update();
+                    for notification in jobs.take_notifications() {
+                        report(notification);
+                    }
+                }
+                signals.extend(taken);
+            }
+            unsafe { close(stop_read_fd) };
+            signals.sort_unstable();
+            signals.dedup();
+
+            (jobs, signals)
+        });
+
+        Ok(JobWatcher { thread, stop_fd })
+    }
+
+    /// Stop watching, give the job table back to `jobs` and return
+    /// the signals taken from the self-pipe, which are still to be
+    /// handled.
+    pub(crate) fn stop(self, jobs: &mut Jobs) -> Vec<c_int> {
+        unsafe {
+            write(self.stop_fd, b"\0".as_ptr() as *const c_void, 1);
+            close(self.stop_fd);
+        }
+        // the jobs are lost with a panicking thread, so panic as well
+        // rather than carry on without them
+        let (watched, signals) = match self.thread.join() {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        *jobs = watched;
+
+        signals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use super::{Job, JobState, JobWatcher, Jobs};
+
+    #[test]
+    fn test_jobs() {
+        let mut jobs = Jobs::default();
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe { libc::_exit(3) };
+        }
+
+        assert_eq!(jobs.add(pid, &[pid], "exit 3".to_owned()), 1);
+        assert_eq!(jobs.add(pid + 1, &[], "true".to_owned()), 2);
+        assert_eq!(jobs.running(), 2);
+
+        for _ in 0..100 {
+            jobs.update();
+            if jobs.running() == 0 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            jobs.take_notifications(),
+            vec![
+                "[1]-  Exit 3                  exit 3",
+                "[2]+  Done                    true",
+            ]
+        );
+        assert_eq!(jobs.running(), 0);
+        // the status stays available for wait, once
+        assert_eq!(jobs.take_reaped(pid), Some(3));
+        assert_eq!(jobs.take_reaped(pid), None);
+        assert_eq!(jobs.add(pid, &[pid], "sleep 1".to_owned()), 1);
+
+        let job = Job {
+            id: 3,
+            pgid: 0,
+            processes: vec![],
+            state: JobState::Signaled(libc::SIGKILL),
+            command: "sleep 10".to_owned(),
+            nohup: false,
+        };
+        assert_eq!(job.format(' '), "[3]   Killed                  sleep 10");
+    }
+
+    #[test]
+    fn test_find_and_wait() {
+        let mut jobs = Jobs::default();
+        let mut pids = Vec::new();
+        for status in [1, 2, 3] {
+            let pid = unsafe { libc::fork() };
+            if pid == 0 {
+                unsafe { libc::_exit(status) };
+            }
+            pids.push(pid);
+        }
+        jobs.add(pids[0], &pids[..1], "make -j8".to_owned());
+        jobs.add(pids[1], &pids[1..2], "sleep 1".to_owned());
+        jobs.add(pids[2], &pids[2..], "sleep 2".to_owned());
+
+        assert_eq!(jobs.find("%1"), Some(0));
+        assert_eq!(jobs.find("%+"), Some(2));
+        assert_eq!(jobs.find("%%"), Some(2));
+        assert_eq!(jobs.find("%-"), Some(1));
+        assert_eq!(jobs.find("%make"), Some(0));
+        assert_eq!(jobs.find("%sleep"), None);
+        assert_eq!(jobs.find("%?2"), Some(2));
+        assert_eq!(jobs.find("%4"), None);
+        assert_eq!(jobs.find("1"), None);
+        assert_eq!(jobs.find_pid(pids[1]), Some(1));
+
+        assert_eq!(jobs.wait(1).unwrap().status(), 2);
+        assert_eq!(jobs.find("%3"), Some(1));
+
+        // finished already, such that wait_any does not suspend, the
+        // SIGCHLD may be delivered to the thread of another test
+        sleep(Duration::from_millis(100));
+        let mut statuses = vec![
+            jobs.wait_any().unwrap().unwrap().status(),
+            jobs.wait_any().unwrap().unwrap().status(),
+        ];
+        statuses.sort();
+        assert_eq!(statuses, vec![1, 3]);
+        assert_eq!(jobs.wait_any(), Ok(None));
+    }
+
+    #[test]
+    fn test_wait_stopped() {
+        let mut jobs = Jobs::default();
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                libc::raise(libc::SIGSTOP);
+                libc::_exit(4);
+            }
+        }
+        jobs.add(pid, &[pid], "stopper".to_owned());
+
+        let job = jobs.wait(0).unwrap();
+        assert_eq!(job.state, JobState::Stopped(libc::SIGSTOP));
+        assert_eq!(job.status(), 128 + libc::SIGSTOP);
+        assert_eq!(jobs.find_pid(pid), Some(0));
+        // returns on the stop again rather than blocking
+        assert_eq!(jobs.wait(0).unwrap().status(), 128 + libc::SIGSTOP);
+        jobs.wait_all().unwrap();
+        assert_eq!(jobs.wait_any(), Ok(None));
+
+        unsafe { libc::kill(pid, libc::SIGCONT) };
+        for _ in 0..100 {
+            jobs.update();
+            if jobs.jobs[0].state != JobState::Stopped(libc::SIGSTOP) {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(jobs.wait(0).unwrap().status(), 4);
+        assert_eq!(jobs.find_pid(pid), None);
+    }
+
+    #[test]
+    fn test_job_watcher() {
+        let mut filedes = [-1; 2];
+        assert_eq!(
+            unsafe { libc::pipe2(filedes.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) },
+            0
+        );
+        let [read_fd, write_fd] = filedes;
+        let mut jobs = Jobs::default();
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe { libc::_exit(3) };
+        }
+        jobs.add(pid, &[pid], "exit 3".to_owned());
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher =
+            JobWatcher::start(&mut jobs, read_fd, move |line| sender.send(line).unwrap()).unwrap();
+        assert_eq!(jobs.len(), 0);
+
+        // as the signal handler does, until the child exited
+        let mut notification = None;
+        for _ in 0..100 {
+            let byte = libc::SIGCHLD as u8;
+            unsafe { libc::write(write_fd, &byte as *const u8 as *const libc::c_void, 1) };
+            if let Ok(line) = receiver.recv_timeout(Duration::from_millis(10)) {
+                notification = Some(line);
+                break;
+            }
+        }
+        assert_eq!(
+            notification.as_deref(),
+            Some("[1]+  Exit 3                  exit 3")
+        );
+
+        assert_eq!(watcher.stop(&mut jobs), vec![libc::SIGCHLD]);
+        assert_eq!(jobs.len(), 0);
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+}
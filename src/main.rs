@@ -1,42 +1,200 @@
+pub mod builtins;
+pub mod conditional;
 pub mod execution;
+pub mod expansion;
+pub mod hash;
+pub mod jobs;
+pub mod options;
 pub mod parser;
+pub mod prompt;
+pub mod script;
+pub mod shell;
 pub mod sig;
+pub mod startup;
+#[cfg(test)]
+pub mod testutil;
+pub mod trap;
 
 use rustyline::error::ReadlineError;
-use rustyline::{history::DefaultHistory, Editor};
+use rustyline::{history::DefaultHistory, Editor, ExternalPrinter};
+use std::env;
 use std::error::Error;
+use std::path::Path;
+use std::process::exit;
 
-use crate::execution::execute;
-use crate::sig::{handler, install_sighandler, mask_sigchld, unmask_sigchld};
+use crate::execution::{execute, exit_shell};
+use crate::jobs::{report_finished_jobs, JobWatcher};
+use crate::prompt::{expand_prompt, init_prompt_variables, PromptHelper};
+use crate::script::execute_file;
+use crate::shell::Shell;
+use crate::sig::{
+    handler, init_signal_pipe, install_sighandler, mask_sigchld, set_disposition, signal_pipe_fd,
+    unmask_sigchld, Disposition, INTERACTIVE_SIGNALS,
+};
+use crate::startup::{parse_arguments, run_startup_files};
+use crate::trap::{handle_pending_signals, handle_signals};
+
+/// Read a line, prompting with the variable `name`. With the notify
+/// option, jobs which finish meanwhile are reported right away, above
+/// the line being edited.
+fn readline(
+    rl: &mut Editor<PromptHelper, DefaultHistory>,
+    shell: &mut Shell,
+    name: &str,
+) -> Result<String, ReadlineError> {
+    let template = shell.var(name).unwrap_or_default().to_owned();
+    let prompt = expand_prompt(shell, &template);
+    if let Some(helper) = rl.helper_mut() {
+        helper.display = prompt.display;
+    }
+
+    if !shell.options.notify {
+        return rl.readline(&prompt.plain);
+    }
+    let mut printer = rl.create_external_printer().ok();
+    let report = move |notification: String| match printer {
+        Some(ref mut printer) => {
+            let _ = printer.print(notification);
+        }
+        None => eprintln!("{}", notification),
+    };
+    let watcher = match JobWatcher::start(&mut shell.jobs, signal_pipe_fd(), report) {
+        Ok(watcher) => watcher,
+        Err(_) => return rl.readline(&prompt.plain),
+    };
+    let readline = rl.readline(&prompt.plain);
+    let signals = watcher.stop(&mut shell.jobs);
+    handle_signals(shell, &signals);
+
+    readline
+}
+
+/// Read a complete command line, prompting with `PS2` for more input
+/// as long as a quoted string or a line continuation is unfinished or
+/// the line ends with `&&`, `||` or `|`.
+fn read_command(
+    rl: &mut Editor<PromptHelper, DefaultHistory>,
+    shell: &mut Shell,
+) -> Result<String, ReadlineError> {
+    let mut line = readline(rl, shell, "PS1")?;
+    while parser::is_incomplete(&line) {
+        let continuation = readline(rl, shell, "PS2")?;
+        if line.ends_with('\\') {
+            line.pop();
+        } else {
+            line.push('\n');
+        }
+        line.push_str(&continuation);
+    }
+
+    Ok(line)
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    init_signal_pipe()?;
     install_sighandler(libc::SIGCHLD, handler)?;
 
     if let Ok(chld_set) = mask_sigchld() {
         unmask_sigchld(chld_set)?;
     }
 
-    let mut rl = Editor::<(), DefaultHistory>::new()?;
+    let args: Vec<String> = env::args().collect();
+    let invocation = match parse_arguments(&args, unsafe { libc::isatty(libc::STDIN_FILENO) } == 1)
+    {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("stsh: {}", e);
+            exit(2);
+        }
+    };
 
+    let mut shell = Shell::from_environment();
+    shell.name = invocation.script.clone().unwrap_or_else(|| args[0].clone());
+    shell.options = invocation.options.clone();
+    shell.interactive = invocation.interactive;
+    // lets benchmarks compare fork with posix_spawn
+    shell.force_fork = env::var_os("STSH_FORCE_FORK").is_some();
+    if shell.interactive {
+        for signum in INTERACTIVE_SIGNALS {
+            set_disposition(signum, Disposition::Ignore)?;
+        }
+        shell.job_control = true;
+        // in a process group of its own in the foreground of the
+        // terminal, the shell can hand the terminal over to its jobs
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+            unsafe {
+                libc::setpgid(0, 0);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            }
+            shell.terminal = Some(libc::STDIN_FILENO);
+        }
+    }
+    init_prompt_variables(&mut shell);
+    run_startup_files(&mut shell, &invocation);
+
+    if let Some(ref script) = invocation.script {
+        shell.positional = invocation.args;
+        match execute_file(&mut shell, Path::new(script)) {
+            Ok(status) => exit_shell(&mut shell, status),
+            Err(e) => {
+                eprintln!("stsh: {}: {}", script, e);
+                exit(127);
+            }
+        }
+    }
+
+    let mut rl = Editor::<PromptHelper, DefaultHistory>::new()?;
+    rl.set_helper(Some(PromptHelper::default()));
+
+    let mut line_no = 1;
     loop {
-        let readline = rl.readline(">> ");
+        handle_pending_signals(&mut shell);
+        report_finished_jobs(&mut shell);
+
+        // the warning about stopped jobs is only skipped by an exit
+        // right after it
+        let exit_warned = shell.exit_warned;
+        let readline = read_command(&mut rl, &mut shell);
+        handle_pending_signals(&mut shell);
         match readline {
             Ok(line) => {
                 rl.add_history_entry(&line)?;
+                shell.line_offset = line_no;
+                line_no += line.matches('\n').count() + 1;
+                let line = parser::expand_aliases(&line, &shell.aliases);
                 match parser::parse(&line) {
-                    Ok((_rest, cmds)) => {
-                        eprintln!("{:?}", cmds);
-                        execute(&cmds, false)?;
+                    Ok((_rest, command_line)) => {
+                        shell.last_status = match execute(&mut shell, &command_line.lists) {
+                            Ok(status) => status,
+                            Err(e) => {
+                                eprintln!("stsh: {}", e);
+                                1
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("stsh: {}", parser::syntax_error(&e));
+                        shell.last_status = 2;
                     }
-                    Err(e) => eprintln!("{:?}", e),
                 };
             }
+            Err(ReadlineError::Interrupted) => shell.last_status = 130,
             Err(ReadlineError::Eof) => {
+                if shell.jobs.has_stopped() && !shell.exit_warned {
+                    eprintln!("There are stopped jobs.");
+                    shell.exit_warned = true;
+                    continue;
+                }
                 break;
             }
             _ => (),
         }
+
+        if exit_warned {
+            shell.exit_warned = false;
+        }
     }
 
-    Ok(())
+    let status = shell.last_status;
+    exit_shell(&mut shell, status);
 }
@@ -0,0 +1,136 @@
+//! Shell options as set by `set` or on the command line
+
+/// Options with their single letter flag, if any, and their long
+/// name as used with `set -o`.
+const OPTIONS: [(Option<char>, &str); 8] = [
+    (Some('e'), "errexit"),
+    (Some('C'), "noclobber"),
+    (Some('n'), "noexec"),
+    (Some('f'), "noglob"),
+    (Some('b'), "notify"),
+    (Some('u'), "nounset"),
+    (None, "pipefail"),
+    (Some('x'), "xtrace"),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Options {
+    /// Exit if a command fails
+    pub errexit: bool,
+    /// Do not overwrite existing files with `>`
+    pub noclobber: bool,
+    /// Read commands, but do not execute them
+    pub noexec: bool,
+    /// Disable pathname expansion
+    pub noglob: bool,
+    /// Report finished background jobs right away, not only before
+    /// the next prompt
+    pub notify: bool,
+    /// Treat the expansion of unset parameters as an error
+    pub nounset: bool,
+    /// The status of a pipeline is the one of its last failing
+    /// command
+    pub pipefail: bool,
+    /// Print commands before they are executed
+    pub xtrace: bool,
+}
+
+impl Options {
+    fn option_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            "noglob" => Some(&mut self.noglob),
+            "notify" => Some(&mut self.notify),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    fn option(&self, name: &str) -> bool {
+        match name {
+            "errexit" => self.errexit,
+            "noclobber" => self.noclobber,
+            "noexec" => self.noexec,
+            "noglob" => self.noglob,
+            "notify" => self.notify,
+            "nounset" => self.nounset,
+            "pipefail" => self.pipefail,
+            "xtrace" => self.xtrace,
+            _ => false,
+        }
+    }
+
+    /// Set the option `name`, return `false` if there is no such
+    /// option.
+    pub(crate) fn set(&mut self, name: &str, value: bool) -> bool {
+        match self.option_mut(name) {
+            Some(option) => {
+                *option = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the option given by its single letter `flag`, return
+    /// `false` if there is no such option.
+    pub(crate) fn set_flag(&mut self, flag: char, value: bool) -> bool {
+        match OPTIONS.iter().find(|(letter, _)| *letter == Some(flag)) {
+            Some((_, name)) => self.set(name, value),
+            None => false,
+        }
+    }
+
+    /// The letters of all active options, as shown by `$-`.
+    pub(crate) fn flags(&self) -> String {
+        OPTIONS
+            .iter()
+            .filter(|(_, name)| self.option(name))
+            .filter_map(|(letter, _)| *letter)
+            .collect()
+    }
+
+    /// All option names with their current value.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        OPTIONS
+            .iter()
+            .map(move |(_, name)| (*name, self.option(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+
+    #[test]
+    fn test_set_options() {
+        let mut options = Options::default();
+        assert_eq!(options.flags(), "");
+
+        assert!(options.set_flag('e', true));
+        assert!(options.set_flag('x', true));
+        assert!(options.set("pipefail", true));
+        assert!(!options.set_flag('p', true));
+        assert!(!options.set("nosuchoption", true));
+
+        assert!(options.errexit);
+        assert!(options.xtrace);
+        assert!(options.pipefail);
+        assert_eq!(options.flags(), "ex");
+
+        assert!(options.set_flag('e', false));
+        assert_eq!(options.flags(), "x");
+        assert_eq!(
+            options
+                .iter()
+                .filter(|(_, value)| *value)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["pipefail", "xtrace"]
+        );
+    }
+}
@@ -1,14 +1,21 @@
 // https://github.com/Geal/nom/blob/master/doc/choosing_a_combinator.md
 // https://github.com/bminor/bash/blob/master/parse.y
 
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::iter;
+
+use crate::expansion::is_name;
+
 use nom::{
     branch::alt,
-    bytes::complete::{escaped, tag, take_while1},
-    character::complete::{alphanumeric1, char, satisfy, space0, space1},
-    combinator::{all_consuming, cond, opt, verify},
-    multi::separated_list0,
-    sequence::delimited,
-    IResult, InputLength,
+    bytes::complete::{tag, take_till},
+    character::complete::{char, space0, space1},
+    combinator::{all_consuming, cond, not, opt, recognize, value},
+    error::{Error, ErrorKind},
+    multi::{many0, separated_list0},
+    sequence::{pair, preceded, terminated},
+    IResult, Offset,
 };
 
 #[derive(Debug, PartialEq)]
@@ -21,77 +28,603 @@ pub(crate) struct InputRedirect<'a> {
 pub(crate) struct OutputRedirect<'a> {
     pub filename: &'a str,
     pub append: bool,
+    /// `>|` overrides the noclobber option
+    pub clobber: bool,
     pub file_descriptor: u8,
 }
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct Command<'a> {
+    /// The first word after the assignments, empty if there is none
     pub name: &'a str,
-    pipe: bool,
+    /// Line the command starts on, counted from 0 within the input
+    pub line: usize,
+    /// Leading words of the form `name=value`
+    pub assignments: Vec<&'a str>,
+    pub pipe: bool,
     pub background: bool,
     pub input_file: Option<InputRedirect<'a>>,
     pub output_file: Option<OutputRedirect<'a>>,
     pub parameters: Vec<&'a str>,
 }
 
-pub(crate) fn parse(input: &str) -> IResult<&str, Vec<Command>> {
-    // let pipe = char('|');
-    // let sep = char(';');
-    // let and = tag("&&");
-    // let or = tag("||");
+/// Commands connected by `|`, optionally negated by `!`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Pipeline<'a> {
+    pub negated: bool,
+    pub commands: Vec<Command<'a>>,
+}
+
+/// Shows the command as it would be typed, without a trailing `&`.
+impl Display for Command<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let words: Vec<&str> = self
+            .assignments
+            .iter()
+            .chain(iter::once(&self.name).filter(|name| !name.is_empty()))
+            .chain(self.parameters.iter())
+            .copied()
+            .collect();
+        write!(f, "{}", words.join(" "))?;
+        if let Some(ref input_redirect) = self.input_file {
+            if input_redirect.file_descriptor != 0 {
+                write!(f, " {}", input_redirect.file_descriptor)?;
+            }
+            write!(f, " < {}", input_redirect.filename)?;
+        }
+        if let Some(ref output_redirect) = self.output_file {
+            write!(f, " ")?;
+            if output_redirect.file_descriptor != 1 {
+                write!(f, "{}", output_redirect.file_descriptor)?;
+            }
+            let operator = if output_redirect.append {
+                ">>"
+            } else if output_redirect.clobber {
+                ">|"
+            } else {
+                ">"
+            };
+            write!(f, "{} {}", operator, output_redirect.filename)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Pipeline<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (idx, command) in self.commands.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Connector {
+    And,
+    Or,
+}
+
+/// A comment, from a `#` at the start of a word to the end of the
+/// line, kept for tools which format the input.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Comment<'a> {
+    /// The comment including the `#`, a slice of the input
+    pub text: &'a str,
+    /// Line the comment is on, counted from 0 within the input
+    pub line: usize,
+    /// Byte offset of the `#` within its line
+    pub column: usize,
+}
+
+/// Pipelines connected by `&&` and `||`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AndOrList<'a> {
+    pub first: Pipeline<'a>,
+    pub rest: Vec<(Connector, Pipeline<'a>)>,
+}
+
+/// The syntax tree of a command line: its and-or lists and, separately,
+/// its comments, both in the order they appear.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CommandLine<'a> {
+    pub lists: Vec<AndOrList<'a>>,
+    pub comments: Vec<Comment<'a>>,
+}
+
+/// Parse a command line, which is a list of and-or lists separated by
+/// `;`, `&` or newlines. Comments may follow each list and fill whole
+/// lines.
+pub(crate) fn parse(input: &str) -> IResult<&str, CommandLine<'_>> {
     // let subshell = delimited(char('('), ..., char(')'));
     // let star = char('*');
     // let questionmark = char('?');
 
-    let (i, command) = all_consuming(parse_command)(input)?;
+    let mut lists = Vec::new();
+    let mut comments = Vec::new();
+    let (mut i, _) = linebreaks(input, &mut comments)?;
+    while !i.is_empty() {
+        let (rest, and_or_list) = and_or(i, &mut comments)?;
+        let background = and_or_list.last_command().background;
+        lists.push(and_or_list);
+
+        let (rest, _) = space0(rest)?;
+        let (rest, separator) = opt(char(';'))(rest)?;
+        let (rest, newline) = linebreaks(rest, &mut comments)?;
+        i = rest;
+        if separator.is_none() && !background && !newline {
+            break;
+        }
+    }
+
+    let (i, _) = all_consuming(space0)(i)?;
+
+    let line_of = |fragment: &str| input[..input.offset(fragment)].matches('\n').count();
+    for and_or_list in &mut lists {
+        let pipelines = iter::once(&mut and_or_list.first)
+            .chain(and_or_list.rest.iter_mut().map(|(_, pipeline)| pipeline));
+        for command in pipelines.flat_map(|pipeline| pipeline.commands.iter_mut()) {
+            command.line = line_of(command.name);
+        }
+    }
+    let comments = comments
+        .into_iter()
+        .map(|text| {
+            let before = &input[..input.offset(text)];
+            Comment {
+                text,
+                line: line_of(text),
+                column: before.len() - before.rfind('\n').map_or(0, |idx| idx + 1),
+            }
+        })
+        .collect();
+
+    Ok((i, CommandLine { lists, comments }))
+}
+
+/// Describe why `parse` failed, naming the token at which parsing
+/// stopped.
+pub(crate) fn syntax_error(error: &nom::Err<Error<&str>>) -> String {
+    let rest = match error {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e.input.trim_start(),
+        nom::Err::Incomplete(_) => "",
+    };
+
+    let token = rest.split_whitespace().next().unwrap_or_default();
+    if token.is_empty() {
+        "syntax error: unexpected end of input".to_owned()
+    } else {
+        format!("syntax error near `{}'", token)
+    }
+}
+
+/// Recognize a comment, from `#` to the end of the line.
+fn comment(input: &str) -> IResult<&str, &str> {
+    recognize(pair(char('#'), take_till(|chr| chr == '\n')))(input)
+}
+
+/// Skip blanks, comments and newlines, collecting the comments in
+/// `comments`. Return whether a newline was skipped.
+fn linebreaks<'a>(input: &'a str, comments: &mut Vec<&'a str>) -> IResult<&'a str, bool> {
+    let mut newline = false;
+    let (mut i, _) = space0(input)?;
+    loop {
+        // words never start with `#`, see `word`
+        if let (rest, Some(text)) = opt(comment)(i)? {
+            comments.push(text);
+            i = rest;
+        }
+        match i.strip_prefix('\n') {
+            Some(rest) => {
+                newline = true;
+                i = space0(rest)?.0;
+            }
+            None => return Ok((i, newline)),
+        }
+    }
+}
+
+impl<'a> AndOrList<'a> {
+    pub(crate) fn last_command(&self) -> &Command<'a> {
+        let pipeline = self
+            .rest
+            .last()
+            .map_or(&self.first, |(_, pipeline)| pipeline);
+        // a pipeline consists of one command at least
+        pipeline.commands.last().unwrap()
+    }
+}
+
+/// Parse pipelines connected by `&&` and `||`. Newlines and comments,
+/// collected in `comments`, may follow a connector.
+fn and_or<'a>(input: &'a str, comments: &mut Vec<&'a str>) -> IResult<&'a str, AndOrList<'a>> {
+    let mut connector = preceded(
+        space0,
+        alt((
+            value(Connector::And, tag("&&")),
+            value(Connector::Or, tag("||")),
+        )),
+    );
+
+    let (mut i, first) = pipeline(input, comments)?;
+    let mut rest = Vec::new();
+    while let (after, Some(connector)) = opt(&mut connector)(i)? {
+        let (after, _) = linebreaks(after, comments)?;
+        let (after, pipeline) = pipeline(after, comments)?;
+        rest.push((connector, pipeline));
+        i = after;
+    }
+
+    Ok((i, AndOrList { first, rest }))
+}
+
+/// Parse commands connected by `|`. Like after `&&`, newlines and
+/// comments may follow the `|`.
+fn pipeline<'a>(input: &'a str, comments: &mut Vec<&'a str>) -> IResult<&'a str, Pipeline<'a>> {
+    let mut pipe = preceded(space0, terminated(char('|'), not(char('|'))));
+
+    let (i, _) = space0(input)?;
+    let (i, negated) = opt(terminated(char('!'), space1))(i)?;
+    let (mut i, command) = parse_command(i)?;
+    let mut commands = vec![command];
+    while let (after, Some(_)) = opt(&mut pipe)(i)? {
+        let (after, _) = linebreaks(after, comments)?;
+        let (after, command) = parse_command(after)?;
+        commands.push(command);
+        i = after;
+    }
+    let last = commands.len() - 1;
+    for command in &mut commands[..last] {
+        command.pipe = true;
+    }
+
+    Ok((
+        i,
+        Pipeline {
+            negated: negated.is_some(),
+            commands,
+        },
+    ))
+}
+
+/// Check whether `input` ends in the middle of a quoted string, with a
+/// line continuation or with `&&`, `||` or `|`, which a command has to
+/// follow, i.e. more input has to be read before it can be parsed.
+/// Quotes, backslashes and operators in comments do not count.
+pub(crate) fn is_incomplete(input: &str) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut in_comment = false;
+    let mut at_word_start = true;
+    let mut continued = false;
+    let mut prev = None;
+    for chr in input.chars() {
+        if in_comment {
+            in_comment = chr != '\n';
+            at_word_start = true;
+            continue;
+        }
+        if escaped {
+            escaped = false;
+            at_word_start = false;
+            continue;
+        }
+
+        match (quote, chr) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => escaped = true,
+            (None, '\'') | (None, '"') => quote = Some(chr),
+            (None, '#') if at_word_start => in_comment = true,
+            _ => (),
+        }
+        at_word_start = quote.is_none() && is_metacharacter(chr);
+        if !in_comment && !chr.is_ascii_whitespace() {
+            continued = quote.is_none()
+                && !escaped
+                && match chr {
+                    // not the `>|` of a redirection
+                    '|' => prev != Some('>'),
+                    '&' => prev == Some('&'),
+                    _ => false,
+                };
+        }
+        prev = Some(chr);
+    }
+
+    escaped || quote.is_some() || continued
+}
+
+/// Return the length of the text enclosed in the parentheses of a
+/// command substitution, `input` starting right after the opening
+/// parenthesis, or `None` if the closing parenthesis is missing.
+pub(crate) fn substitution_len(input: &str) -> Option<usize> {
+    let mut depth = 1;
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, chr) in input.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
 
-    Ok((i, vec![command]))
+        match (quote, chr) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('\''), _) => (),
+            (_, '\\') => escaped = true,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') => quote = Some(chr),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
 }
 
-fn is_allowed_in_double_quotes(chr: char) -> bool {
-    chr.is_alphanumeric() || chr.is_whitespace()
+/// Replace aliases in `input` by their values. Aliases are expanded
+/// where a command name is expected: at the start of each command,
+/// after leading assignments and, if the value of an alias ends in a
+/// blank, for the word following it. An alias is not expanded again
+/// within its own value.
+pub(crate) fn expand_aliases(input: &str, aliases: &BTreeMap<String, String>) -> String {
+    let mut output = String::with_capacity(input.len());
+    substitute_aliases(input, aliases, &mut Vec::new(), true, &mut output);
+
+    output
 }
 
-fn parse_command(input: &str) -> IResult<&str, Command> {
-    let single_quote = char('\'');
-    let double_quote = char('"');
-    let background = char('&');
+/// Append `input` with aliases expanded to `output`, the first word
+/// being a command name if `command_position`. `active` are the aliases
+/// currently being expanded. Return whether a word at the end would
+/// be a command name.
+fn substitute_aliases<'a>(
+    input: &'a str,
+    aliases: &'a BTreeMap<String, String>,
+    active: &mut Vec<&'a str>,
+    mut command_position: bool,
+    output: &mut String,
+) -> bool {
+    let mut rest = input;
+    while let Some(chr) = rest.chars().next() {
+        let len = match chr {
+            ' ' | '\t' => 1,
+            // a comment lasts until the end of the line
+            '#' => rest.find('\n').unwrap_or(rest.len()),
+            '\n' | ';' | '&' | '|' | '(' | ')' => {
+                command_position = true;
+                1
+            }
+            '<' | '>' => {
+                command_position = false;
+                1
+            }
+            '!' if command_position => 1,
+            _ => match word(rest) {
+                Ok((remaining, word)) => {
+                    let value = aliases.get(word).filter(|_| !active.contains(&word));
+                    match value {
+                        Some(value) if command_position => {
+                            active.push(word);
+                            let pending = substitute_aliases(value, aliases, active, true, output);
+                            active.pop();
+                            command_position = pending || value.ends_with([' ', '\t']);
+                        }
+                        _ => {
+                            output.push_str(word);
+                            command_position &=
+                                word.find('=').is_some_and(|idx| is_name(&word[..idx]));
+                        }
+                    }
+                    rest = remaining;
+                    continue;
+                }
+                Err(_) => {
+                    command_position = false;
+                    chr.len_utf8()
+                }
+            },
+        };
+
+        output.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+
+    command_position
+}
+
+fn is_metacharacter(chr: char) -> bool {
+    matches!(
+        chr,
+        ' ' | '\t' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')'
+    )
+}
+
+/// Recognize a word, e.g. a command name or a parameter. Quotes,
+/// escapes and substitutions are kept as they are, they are removed
+/// or replaced during expansion.
+fn word(input: &str) -> IResult<&str, &str> {
+    let error = |i| Err(nom::Err::Error(Error::new(i, ErrorKind::Verify)));
+
+    // a word must not start like a comment
+    if input.starts_with('#') {
+        return error(input);
+    }
+
+    let mut len = 0;
+    let mut chars = input.char_indices().peekable();
+    while let Some((idx, chr)) = chars.next() {
+        let rest = &input[idx + chr.len_utf8()..];
+        let consumed = match chr {
+            '\\' => rest.chars().next().map_or(0, char::len_utf8),
+            '\'' => match rest.find('\'') {
+                Some(end) => end + 1,
+                None => return error(input),
+            },
+            '"' => match double_quoted_len(rest) {
+                Some(end) => end + 1,
+                None => return error(input),
+            },
+            '$' if rest.starts_with('(') => match substitution_len(&rest[1..]) {
+                Some(end) => end + 2,
+                None => return error(input),
+            },
+            '$' if rest.starts_with('{') => match rest.find('}') {
+                Some(end) => end + 1,
+                None => return error(input),
+            },
+            // process substitution
+            '<' | '>' if rest.starts_with('(') => match substitution_len(&rest[1..]) {
+                Some(end) => end + 2,
+                None => return error(input),
+            },
+            chr if is_metacharacter(chr) => break,
+            _ => 0,
+        };
+
+        len = idx + chr.len_utf8() + consumed;
+        while chars.peek().is_some_and(|(idx, _)| *idx < len) {
+            chars.next();
+        }
+    }
+
+    if len == 0 {
+        return error(input);
+    }
+
+    Ok((&input[len..], &input[..len]))
+}
+
+/// Recognize the regular expression operand of `=~` within `[[ ]]`, in
+/// which `(`, `)`, `|`, `<` and `>` are ordinary characters.
+fn regex_word(input: &str) -> IResult<&str, &str> {
+    let mut len = 0;
+    while let Some(chr) = input[len..].chars().next() {
+        if matches!(chr, '(' | ')' | '|' | '<' | '>') {
+            len += 1;
+        } else if let Ok((rest, _)) = word(&input[len..]) {
+            len = input.offset(rest);
+        } else {
+            break;
+        }
+    }
+
+    if len == 0 {
+        return Err(nom::Err::Error(Error::new(input, ErrorKind::Verify)));
+    }
+
+    Ok((&input[len..], &input[..len]))
+}
+
+/// Recognize the words of a conditional command following `[[`, up to
+/// and including `]]`. Within `[[ ]]`, `&&`, `||`, `(`, `)`, `<` and `>`
+/// are words of their own rather than control operators and
+/// redirections.
+fn conditional_words(input: &str) -> IResult<&str, Vec<&str>> {
+    let mut operator = alt((tag("&&"), tag("||"), tag("("), tag(")"), tag("<"), tag(">")));
+
+    let mut words = Vec::new();
+    let mut i = input;
+    loop {
+        i = space0(i)?.0;
+        let (rest, word) = if words.last() == Some(&"=~") {
+            regex_word(i)?
+        } else {
+            alt((&mut operator, word))(i)?
+        };
+        words.push(word);
+        i = rest;
+        if word == "]]" {
+            return Ok((i, words));
+        }
+    }
+}
+
+/// Recognize an assignment, a word starting with a variable name
+/// followed by `=`.
+fn assignment(input: &str) -> IResult<&str, &str> {
+    let (i, word) = word(input)?;
+    match word.find('=') {
+        Some(idx) if is_name(&word[..idx]) => Ok((i, word)),
+        _ => Err(nom::Err::Error(Error::new(input, ErrorKind::Verify))),
+    }
+}
+
+/// Return the length of a double quoted string up to the closing
+/// quote, `input` starting right after the opening quote.
+fn double_quoted_len(input: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (idx, chr) in input.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if chr == '\\' {
+            escaped = true;
+        } else if chr == '"' {
+            return Some(idx);
+        } else if chr == '$' && input[idx + 1..].starts_with('(') {
+            // a command substitution may contain quotes of its own
+            let len = substitution_len(&input[idx + 2..])?;
+            return double_quoted_len(&input[idx + len + 3..])
+                .map(|rest_len| idx + len + 3 + rest_len);
+        }
+    }
+
+    None
+}
+
+fn parse_command(input: &str) -> IResult<&str, Command<'_>> {
+    let background = terminated(char('&'), not(char('&')));
     let input_redirect = char('<');
     let output_redirect = tag(">");
     let output_redirect_append = tag(">>");
+    let output_redirect_clobber = tag(">|");
     let error_redirect = tag("2>"); // => it should be [n]>, where n can be any file descriptor, I guess
 
-    let mut command = verify(escaped(alphanumeric1, '\\', &double_quote), |s: &str| {
-        !s.is_empty()
-    });
-
-    let unquoted_param = verify(escaped(alphanumeric1, '\\', &double_quote), |s: &str| {
-        !s.is_empty()
-    });
-
-    let param_within_quotes = take_while1(is_allowed_in_double_quotes);
-    let quoted_param = alt((
-        delimited(&double_quote, &param_within_quotes, &double_quote),
-        delimited(&single_quote, &param_within_quotes, &single_quote),
-    ));
+    // a parameter must not swallow the file descriptor of a
+    // redirection like "2>"
+    let param = preceded(not(tag("2>")), word);
 
     let (i, _) = space0(input)?; // ignore all leading whitespace
-    let (i, command_name) = command(i)?;
-    // todo!("allow / in command names");
+    let (i, assignments) = many0(terminated(assignment, space0))(i)?;
+    let (i, command_name) = if assignments.is_empty() {
+        word(i)?
+    } else {
+        let (rest, command_name) = opt(word)(i)?;
+        (rest, command_name.unwrap_or(&i[..0]))
+    };
     let (i, _) = space0(i)?;
-    let (i, parameters) = separated_list0(space1, alt((quoted_param, unquoted_param)))(i)?;
+    let (i, parameters) = if command_name == "[[" {
+        conditional_words(i)?
+    } else {
+        separated_list0(space1, param)(i)?
+    };
     let (i, _) = space0(i)?;
     let (i, has_input_redirect) = opt(input_redirect)(i)?;
     let (i, _) = space0(i)?;
-    let (i, input_file) = cond(has_input_redirect.is_some(), alphanumeric1)(i)?;
+    let (i, input_file) = cond(has_input_redirect.is_some(), word)(i)?;
     let (i, _) = space0(i)?;
     let (i, has_output_redirect) = opt(alt((
         error_redirect,
         output_redirect_append,
+        output_redirect_clobber,
         output_redirect,
     )))(i)?;
     let (i, _) = space0(i)?;
-    let (i, output_file) = cond(has_output_redirect.is_some(), alphanumeric1)(i)?;
+    let (i, output_file) = cond(has_output_redirect.is_some(), word)(i)?;
     let (i, _) = space0(i)?;
     let (i, background) = opt(background)(i)?;
     let (i, _) = space0(i)?; // ignore all trailing whitespace
@@ -100,6 +633,9 @@ fn parse_command(input: &str) -> IResult<&str, Command> {
         i,
         Command {
             name: command_name,
+            // set by `parse`, which knows the whole input
+            line: 0,
+            assignments,
             pipe: false,
             background: background.is_some(),
             input_file: input_file.map(|file| InputRedirect {
@@ -109,19 +645,22 @@ fn parse_command(input: &str) -> IResult<&str, Command> {
             output_file: output_file.map(|file| OutputRedirect {
                 filename: file,
                 append: has_output_redirect == Some(">>"),
+                clobber: has_output_redirect == Some(">|"),
                 file_descriptor: if has_output_redirect == Some("2>") {
                     2
                 } else {
                     1
                 },
             }),
-            parameters: parameters,
+            parameters,
         },
     ))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     #[test]
     fn test_parse_commands() {
         let result = super::parse("foo bar &");
@@ -131,16 +670,168 @@ mod tests {
             result,
             Ok((
                 "",
-                vec![super::Command {
-                    name: "foo",
-                    pipe: false,
-                    background: true,
-                    input_file: None,
-                    output_file: None,
-                    parameters: vec!["bar"]
-                }]
+                super::CommandLine {
+                    lists: vec![super::AndOrList {
+                        first: super::Pipeline {
+                            negated: false,
+                            commands: vec![super::Command {
+                                name: "foo",
+                                line: 0,
+                                assignments: vec![],
+                                pipe: false,
+                                background: true,
+                                input_file: None,
+                                output_file: None,
+                                parameters: vec!["bar"]
+                            }]
+                        },
+                        rest: vec![],
+                    }],
+                    comments: vec![],
+                }
             ))
         );
+
+        assert_eq!(super::parse("  "), Ok(("", super::CommandLine::default())));
+        assert!(super::parse("a;;").is_err());
+        assert!(super::parse("a |").is_err());
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let error = super::parse("ls )").unwrap_err();
+        assert_eq!(super::syntax_error(&error), "syntax error near `)'");
+
+        let error = nom::Err::Error(nom::error::Error::new(" ", nom::error::ErrorKind::Eof));
+        assert_eq!(
+            super::syntax_error(&error),
+            "syntax error: unexpected end of input"
+        );
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let comment = |text, line, column| super::Comment { text, line, column };
+
+        assert_eq!(
+            super::parse("# only a comment"),
+            Ok((
+                "",
+                super::CommandLine {
+                    lists: vec![],
+                    comments: vec![comment("# only a comment", 0, 0)],
+                }
+            ))
+        );
+
+        let (rest, command_line) = super::parse("a b#c # d; e").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(command_line.lists.len(), 1);
+        assert_eq!(
+            command_line.lists[0].first.commands[0].parameters,
+            vec!["b#c"]
+        );
+        assert_eq!(command_line.comments, vec![comment("# d; e", 0, 6)]);
+
+        let (_, command_line) = super::parse("a > out; b &#c").unwrap();
+        assert_eq!(command_line.lists.len(), 2);
+        assert_eq!(command_line.comments, vec![comment("#c", 0, 12)]);
+
+        let (_, command_line) = super::parse("echo 'x\ny' z  # w").unwrap();
+        assert_eq!(command_line.comments, vec![comment("# w", 1, 6)]);
+
+        assert!(super::parse("a && # b").is_err());
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let input = "# first\n\n  a # second\n  # third\nb; c &\n#fourth\n";
+        let (rest, command_line) = super::parse(input).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(command_line.lists.len(), 3);
+        let lines: Vec<(&str, usize)> = command_line
+            .lists
+            .iter()
+            .map(|list| (list.first.commands[0].name, list.first.commands[0].line))
+            .collect();
+        assert_eq!(lines, vec![("a", 2), ("b", 4), ("c", 4)]);
+        assert!(command_line.lists[2].last_command().background);
+        assert_eq!(
+            command_line.comments,
+            vec![
+                super::Comment {
+                    text: "# first",
+                    line: 0,
+                    column: 0
+                },
+                super::Comment {
+                    text: "# second",
+                    line: 2,
+                    column: 4
+                },
+                super::Comment {
+                    text: "# third",
+                    line: 3,
+                    column: 2
+                },
+                super::Comment {
+                    text: "#fourth",
+                    line: 5,
+                    column: 0
+                },
+            ]
+        );
+
+        assert!(super::parse("a\n;").is_err());
+        assert!(super::parse("a\n)").is_err());
+    }
+
+    #[test]
+    fn test_parse_continued_lists() {
+        let (rest, command_line) = super::parse("a &&\n  b ||  # c\n\n d |\n# e\nf").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(command_line.lists.len(), 1);
+        let list = &command_line.lists[0];
+        assert_eq!(names(&list.first), vec!["a"]);
+        assert_eq!(names(&list.rest[0].1), vec!["b"]);
+        assert_eq!(names(&list.rest[1].1), vec!["d", "f"]);
+        assert_eq!(list.rest[1].1.commands[1].line, 5);
+        let comments: Vec<&str> = command_line
+            .comments
+            .iter()
+            .map(|comment| comment.text)
+            .collect();
+        assert_eq!(comments, vec!["# c", "# e"]);
+
+        assert!(super::parse("a &&\n").is_err());
+        assert!(super::parse("a |\n;").is_err());
+    }
+
+    fn names<'a>(pipeline: &super::Pipeline<'a>) -> Vec<&'a str> {
+        pipeline.commands.iter().map(|cmd| cmd.name).collect()
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let list = super::parse("a | b|c; ! d && e || f & g;").unwrap().1.lists;
+        assert_eq!(list.len(), 3);
+
+        assert_eq!(names(&list[0].first), vec!["a", "b", "c"]);
+        assert!(list[0].first.commands[0].pipe);
+        assert!(list[0].first.commands[1].pipe);
+        assert!(!list[0].first.commands[2].pipe);
+        assert!(list[0].rest.is_empty());
+
+        assert!(list[1].first.negated);
+        assert_eq!(names(&list[1].first), vec!["d"]);
+        assert_eq!(list[1].rest.len(), 2);
+        assert_eq!(list[1].rest[0].0, super::Connector::And);
+        assert_eq!(names(&list[1].rest[0].1), vec!["e"]);
+        assert_eq!(list[1].rest[1].0, super::Connector::Or);
+        assert!(list[1].last_command().background);
+
+        assert_eq!(names(&list[2].first), vec!["g"]);
+        assert!(!list[2].last_command().background);
     }
 
     #[test]
@@ -151,6 +842,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: true,
                     input_file: None,
@@ -166,6 +859,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: true,
                     input_file: None,
@@ -181,6 +876,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
@@ -196,11 +893,13 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: true,
                     input_file: None,
                     output_file: None,
-                    parameters: vec!["x", "y", "n m", "s t"]
+                    parameters: vec!["x", "y", "\"n m\"", "'s t'"]
                 }
             ))
         );
@@ -212,6 +911,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: true,
                     input_file: None,
@@ -230,6 +931,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: Some(super::InputRedirect {
@@ -248,12 +951,15 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
                     output_file: Some(super::OutputRedirect {
                         filename: "output",
                         append: false,
+                        clobber: false,
                         file_descriptor: 1
                     }),
                     parameters: vec![]
@@ -267,12 +973,37 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
                     output_file: Some(super::OutputRedirect {
                         filename: "output",
                         append: true,
+                        clobber: false,
+                        file_descriptor: 1
+                    }),
+                    parameters: vec![]
+                }
+            ))
+        );
+
+        assert_eq!(
+            super::parse_command("abc >| output"),
+            Ok((
+                "",
+                super::Command {
+                    name: "abc",
+                    line: 0,
+                    assignments: vec![],
+                    pipe: false,
+                    background: false,
+                    input_file: None,
+                    output_file: Some(super::OutputRedirect {
+                        filename: "output",
+                        append: false,
+                        clobber: true,
                         file_descriptor: 1
                     }),
                     parameters: vec![]
@@ -286,12 +1017,15 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
                     output_file: Some(super::OutputRedirect {
                         filename: "erroroutput",
                         append: false,
+                        clobber: false,
                         file_descriptor: 2
                     }),
                     parameters: vec![]
@@ -305,12 +1039,15 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: true,
                     input_file: None,
                     output_file: Some(super::OutputRedirect {
                         filename: "output",
                         append: false,
+                        clobber: false,
                         file_descriptor: 1
                     }),
                     parameters: vec![]
@@ -324,6 +1061,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "abc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: Some(super::InputRedirect {
@@ -333,6 +1072,7 @@ mod tests {
                     output_file: Some(super::OutputRedirect {
                         filename: "output",
                         append: false,
+                        clobber: false,
                         file_descriptor: 1
                     }),
                     parameters: vec![]
@@ -341,6 +1081,160 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_display() {
+        let list = super::parse("! a 'b c' | d < in 2> err &").unwrap().1.lists;
+        assert_eq!(list[0].first.to_string(), "! a 'b c' | d < in 2> err");
+
+        let list = super::parse("a >| out").unwrap().1.lists;
+        assert_eq!(list[0].first.to_string(), "a >| out");
+
+        let list = super::parse("A=1 B= a C=2").unwrap().1.lists;
+        assert_eq!(list[0].first.to_string(), "A=1 B= a C=2");
+    }
+
+    #[test]
+    fn test_assignments() {
+        let (rest, command) = super::parse_command("A=1 B='x y' cmd C=2").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(command.assignments, vec!["A=1", "B='x y'"]);
+        assert_eq!(command.name, "cmd");
+        assert_eq!(command.parameters, vec!["C=2"]);
+
+        let (_, command) = super::parse_command("A=1 > out").unwrap();
+        assert_eq!(command.assignments, vec!["A=1"]);
+        assert_eq!(command.name, "");
+        assert_eq!(command.output_file.unwrap().filename, "out");
+
+        let (_, command) = super::parse_command("1A=x =y").unwrap();
+        assert!(command.assignments.is_empty());
+        assert_eq!(command.name, "1A=x");
+    }
+
+    #[test]
+    fn test_word() {
+        assert_eq!(super::word("./lib.sh x"), Ok((" x", "./lib.sh")));
+        assert_eq!(super::word("-l>out"), Ok((">out", "-l")));
+        assert_eq!(super::word("a'b c'\\ d e"), Ok((" e", "a'b c'\\ d")));
+        assert_eq!(
+            super::word("\"$(echo \")\")\"&"),
+            Ok(("&", "\"$(echo \")\")\""))
+        );
+        assert_eq!(super::word("$(a (b) c)d e"), Ok((" e", "$(a (b) c)d")));
+        assert_eq!(super::word("${a b}"), Ok(("", "${a b}")));
+        assert_eq!(super::word("<(a (b) c) d"), Ok((" d", "<(a (b) c)")));
+        assert_eq!(super::word("x>(a)|b"), Ok(("|b", "x>(a)")));
+        assert!(super::word("<(a").is_err());
+        assert!(super::word("'abc").is_err());
+        assert_eq!(super::word("a#b $#"), Ok((" $#", "a#b")));
+        assert!(super::word("&").is_err());
+        assert!(super::word("#a").is_err());
+    }
+
+    #[test]
+    fn test_conditional_words() {
+        let list = super::parse("[[ (a<b)&&! -f 'c d' || x =~ ^(y|z)$ ]] > out &")
+            .unwrap()
+            .1
+            .lists;
+        let command = &list[0].first.commands[0];
+        assert_eq!(command.name, "[[");
+        assert_eq!(
+            command.parameters,
+            vec![
+                "(", "a", "<", "b", ")", "&&", "!", "-f", "'c d'", "||", "x", "=~", "^(y|z)$", "]]"
+            ]
+        );
+        assert_eq!(command.output_file.as_ref().unwrap().filename, "out");
+        assert!(command.background);
+
+        assert!(super::parse("[[ a").is_err());
+        assert!(super::parse("[[ a ]]b").is_err());
+        assert!(super::parse("'[[' a").is_ok());
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let list = super::parse("a 'x\ny' | b; c \"\n\n\" && d")
+            .unwrap()
+            .1
+            .lists;
+        let lines: Vec<usize> = list
+            .iter()
+            .flat_map(|and_or_list| {
+                std::iter::once(&and_or_list.first)
+                    .chain(and_or_list.rest.iter().map(|(_, pipeline)| pipeline))
+            })
+            .flat_map(|pipeline| pipeline.commands.iter().map(|command| command.line))
+            .collect();
+        assert_eq!(lines, vec![0, 1, 1, 3]);
+    }
+
+    #[test]
+    fn test_expand_aliases() {
+        let aliases: BTreeMap<String, String> = [
+            ("ll", "ls -l"),
+            ("ls", "ls --color"),
+            ("sudo", "sudo "),
+            ("loop1", "loop2"),
+            ("loop2", "loop1 x"),
+            ("seq", "a; b"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let expand = |input| super::expand_aliases(input, &aliases);
+
+        assert_eq!(expand("ll /tmp"), "ls --color -l /tmp");
+        assert_eq!(expand("ll ll"), "ls --color -l ll");
+        assert_eq!(expand("'ll' \\ll"), "'ll' \\ll");
+        assert_eq!(
+            expand("a ll; ll|ll && ! ll &"),
+            "a ll; ls --color -l|ls --color -l && ! ls --color -l &"
+        );
+        assert_eq!(expand("A=1  ll"), "A=1  ls --color -l");
+        assert_eq!(expand("sudo ll"), "sudo  ls --color -l");
+        assert_eq!(expand("loop1"), "loop1 x");
+        assert_eq!(expand("seq c"), "a; b c");
+        assert_eq!(expand("x > ll"), "x > ll");
+        assert_eq!(expand("x # ll"), "x # ll");
+        assert_eq!(expand("echo $(ll)"), "echo $(ll)");
+    }
+
+    #[test]
+    fn test_substitution_len() {
+        assert_eq!(super::substitution_len("abc)"), Some(3));
+        assert_eq!(super::substitution_len("a(b)c) d"), Some(5));
+        assert_eq!(super::substitution_len("a \")\" b)"), Some(7));
+        assert_eq!(super::substitution_len("abc"), None);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(!super::is_incomplete("abc x"));
+        assert!(!super::is_incomplete("abc \"x y\" 'z'"));
+        assert!(!super::is_incomplete("abc \\\""));
+        assert!(super::is_incomplete("abc \"x"));
+        assert!(super::is_incomplete("abc 'x \""));
+        assert!(super::is_incomplete("abc \\"));
+        assert!(!super::is_incomplete("abc # it's \\"));
+        assert!(!super::is_incomplete("# \"x\nabc"));
+        assert!(super::is_incomplete("abc#'x"));
+        assert!(super::is_incomplete("abc $#\"x"));
+
+        assert!(super::is_incomplete("a &&"));
+        assert!(super::is_incomplete("a ||\n"));
+        assert!(super::is_incomplete("a|"));
+        assert!(super::is_incomplete("a | # b"));
+        assert!(!super::is_incomplete("a &"));
+        assert!(!super::is_incomplete("a & &"));
+        assert!(!super::is_incomplete("a >| b"));
+        assert!(!super::is_incomplete("a '&&'"));
+        assert!(!super::is_incomplete("a \\|"));
+        assert!(!super::is_incomplete("a # b &&"));
+        assert!(!super::is_incomplete("a &&\nb"));
+    }
+
     #[test]
     fn test_parse_quoted_double_quote() {
         assert_eq!(
@@ -349,11 +1243,13 @@ mod tests {
                 "",
                 super::Command {
                     name: "a\\\"bc",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
                     output_file: None,
-                    parameters: vec!["x y"]
+                    parameters: vec!["\"x y\""]
                 }
             ))
         );
@@ -364,6 +1260,8 @@ mod tests {
                 "",
                 super::Command {
                     name: "cmd",
+                    line: 0,
+                    assignments: vec![],
                     pipe: false,
                     background: false,
                     input_file: None,
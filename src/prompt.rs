@@ -0,0 +1,320 @@
+//! Prompt expansion
+//!
+//! https://www.gnu.org/software/bash/manual/html_node/Controlling-the-Prompt.html
+
+use std::borrow::Cow;
+use std::env;
+use std::ffi::CStr;
+use std::mem::MaybeUninit;
+
+use libc::{c_char, geteuid, gethostname, getpwuid, localtime_r, time};
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Helper;
+
+use crate::expansion::expand_dollar;
+use crate::shell::Shell;
+
+pub(crate) const DEFAULT_PS1: &str = ">> ";
+pub(crate) const DEFAULT_PS2: &str = "> ";
+pub(crate) const DEFAULT_PS4: &str = "+ ";
+
+/// Give the prompt variables their default value, unless they are
+/// already set, e.g. inherited from the environment.
+pub(crate) fn init_prompt_variables(shell: &mut Shell) {
+    for (name, default) in [
+        ("PS1", DEFAULT_PS1),
+        ("PS2", DEFAULT_PS2),
+        ("PS4", DEFAULT_PS4),
+    ] {
+        if shell.var(name).is_none() {
+            shell.set_var(name, default);
+        }
+    }
+}
+
+/// An expanded prompt.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Prompt {
+    /// What is written to the terminal, including escape sequences.
+    pub display: String,
+    /// The visible characters only, which determine the width of the
+    /// prompt.
+    pub plain: String,
+}
+
+impl Prompt {
+    fn push_str(&mut self, s: &str, visible: bool) {
+        self.display.push_str(s);
+        if visible {
+            self.plain.push_str(s);
+        }
+    }
+
+    fn push(&mut self, chr: char, visible: bool) {
+        self.display.push(chr);
+        if visible {
+            self.plain.push(chr);
+        }
+    }
+}
+
+/// Remove ANSI escape sequences (CSI, OSC and two character
+/// sequences) from `s`.
+pub(crate) fn strip_ansi(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(chr) = chars.next() {
+        if chr != '\x1b' {
+            result.push(chr);
+            continue;
+        }
+
+        match chars.next() {
+            Some('[') => {
+                // parameter and intermediate bytes until the final byte
+                for chr in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&chr) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                // terminated by BEL or ST (ESC \)
+                while let Some(chr) = chars.next() {
+                    if chr == '\x07' {
+                        break;
+                    } else if chr == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    result
+}
+
+fn user_name() -> String {
+    let passwd = unsafe { getpwuid(geteuid()) };
+    if passwd.is_null() {
+        return env::var("USER").unwrap_or_default();
+    }
+
+    unsafe { CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn host_name() -> String {
+    let mut buf = [0 as c_char; 256];
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len() - 1) } == -1 {
+        return String::new();
+    }
+
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The current working directory with `$HOME` abbreviated by `~`.
+fn working_directory(shell: &Shell) -> String {
+    let cwd = match shell.var("PWD") {
+        Some(pwd) if !pwd.is_empty() => pwd.to_owned(),
+        _ => env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+
+    match shell.var("HOME") {
+        Some(home) if !home.is_empty() && home != "/" => match cwd.strip_prefix(home) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+            _ => cwd,
+        },
+        _ => cwd,
+    }
+}
+
+fn current_time() -> String {
+    let tm = unsafe {
+        let now = time(std::ptr::null_mut());
+        let mut tm = MaybeUninit::zeroed();
+        localtime_r(&now, tm.as_mut_ptr());
+        tm.assume_init()
+    };
+
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+/// Expand the prompt string `template`.
+///
+/// Backslash escapes are decoded and parameter expansion and command
+/// substitution are performed. Everything between `\[` and `\]` as
+/// well as ANSI escape sequences do not count towards the width of
+/// the prompt.
+pub(crate) fn expand_prompt(shell: &mut Shell, template: &str) -> Prompt {
+    let jobs = shell.jobs.running();
+    let shell = &*shell;
+
+    let mut prompt = Prompt::default();
+    let mut visible = true;
+    let mut rest = template;
+    while let Some(chr) = rest.chars().next() {
+        rest = &rest[chr.len_utf8()..];
+        match chr {
+            '\\' => {
+                let escape = match rest.chars().next() {
+                    Some(escape) => escape,
+                    None => {
+                        prompt.push('\\', visible);
+                        break;
+                    }
+                };
+                rest = &rest[escape.len_utf8()..];
+
+                match escape {
+                    '\\' => prompt.push('\\', visible),
+                    'a' => prompt.push('\x07', visible),
+                    'e' => prompt.push('\x1b', visible),
+                    'n' => prompt.push('\n', visible),
+                    'r' => prompt.push('\r', visible),
+                    's' => prompt.push_str("stsh", visible),
+                    'u' => prompt.push_str(&user_name(), visible),
+                    'h' => {
+                        let host = host_name();
+                        prompt.push_str(host.split('.').next().unwrap_or_default(), visible);
+                    }
+                    'H' => prompt.push_str(&host_name(), visible),
+                    'w' => prompt.push_str(&working_directory(shell), visible),
+                    'W' => {
+                        let cwd = working_directory(shell);
+                        let basename = match cwd.rfind('/') {
+                            Some(idx) if cwd.len() > 1 => &cwd[idx + 1..],
+                            _ => &cwd,
+                        };
+                        prompt.push_str(basename, visible);
+                    }
+                    '$' => prompt.push(if unsafe { geteuid() } == 0 { '#' } else { '$' }, visible),
+                    't' => prompt.push_str(&current_time(), visible),
+                    'j' => prompt.push_str(&jobs.to_string(), visible),
+                    '?' => prompt.push_str(&shell.last_status.to_string(), visible),
+                    '[' => visible = false,
+                    ']' => visible = true,
+                    '0'..='7' => {
+                        let digits = rest
+                            .char_indices()
+                            .take(2)
+                            .take_while(|(_, c)| ('0'..='7').contains(c))
+                            .count();
+                        let octal = &template[template.len() - rest.len() - 1..][..=digits];
+                        rest = &rest[digits..];
+                        match u8::from_str_radix(octal, 8) {
+                            Ok(byte) if byte.is_ascii() => prompt.push(byte as char, visible),
+                            _ => (),
+                        }
+                    }
+                    _ => {
+                        prompt.push('\\', visible);
+                        prompt.push(escape, visible);
+                    }
+                }
+            }
+            '$' => match expand_dollar(shell, rest) {
+                Ok(Some((expansion, len))) => {
+                    prompt.push_str(&expansion, visible);
+                    rest = &rest[len..];
+                }
+                Ok(None) => prompt.push('$', visible),
+                Err(e) => {
+                    eprintln!("stsh: {}", e);
+                    prompt.push('$', visible);
+                }
+            },
+            _ => prompt.push(chr, visible),
+        }
+    }
+
+    prompt.plain = strip_ansi(&prompt.plain);
+
+    prompt
+}
+
+/// Line editor helper which shows the prompt including its escape
+/// sequences, while the line editor computes the layout from the
+/// plain prompt only.
+#[derive(Default)]
+pub(crate) struct PromptHelper {
+    pub display: String,
+}
+
+impl Highlighter for PromptHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        if default {
+            Cow::Borrowed(&self.display)
+        } else {
+            Cow::Borrowed(prompt)
+        }
+    }
+}
+
+impl Completer for PromptHelper {
+    type Candidate = String;
+}
+
+impl Hinter for PromptHelper {
+    type Hint = String;
+}
+
+impl Validator for PromptHelper {}
+
+impl Helper for PromptHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::Prompt;
+    use crate::shell::Shell;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(super::strip_ansi("abc"), "abc");
+        assert_eq!(super::strip_ansi("\x1b[1;32mabc\x1b[0m"), "abc");
+        assert_eq!(super::strip_ansi("\x1b]0;title\x07$ "), "$ ");
+        assert_eq!(super::strip_ansi("\x1b]0;title\x1b\\$ "), "$ ");
+    }
+
+    #[test]
+    fn test_expand_prompt() {
+        let mut shell = Shell::default();
+        shell.set_var("HOME", "/home/user");
+        shell.set_var("PWD", "/home/user/src");
+        shell.set_var("NAME", "stsh");
+        shell.last_status = 127;
+
+        assert_eq!(
+            super::expand_prompt(&mut shell, "\\w \\W \\? $NAME> "),
+            Prompt {
+                display: "~/src src 127 stsh> ".to_owned(),
+                plain: "~/src src 127 stsh> ".to_owned(),
+            }
+        );
+
+        assert_eq!(
+            super::expand_prompt(&mut shell, "\\[\\e[32m\\]\\j\\[\\033[0m\\] \\\\"),
+            Prompt {
+                display: "\x1b[32m0\x1b[0m \\".to_owned(),
+                plain: "0 \\".to_owned(),
+            }
+        );
+
+        // raw escape sequences outside of \[ \] do not count either
+        assert_eq!(super::expand_prompt(&mut shell, "\\e[1m>\\e[0m").plain, ">");
+    }
+}
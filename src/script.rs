@@ -0,0 +1,155 @@
+//! Execute shell scripts
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use libc::c_int;
+
+use crate::execution::execute;
+use crate::parser;
+use crate::shell::Shell;
+
+/// Split `source` into complete command lines. Each command line is
+/// returned together with the number of the line it starts on.
+/// Quoted strings and line continuations may span several lines,
+/// blank lines are skipped.
+pub(crate) fn command_lines(source: &str) -> Vec<(usize, String)> {
+    let mut command_lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (idx, line) in source.lines().enumerate() {
+        let (line_no, mut command_line) = match pending.take() {
+            Some((line_no, mut command_line)) => {
+                if command_line.ends_with('\\') {
+                    command_line.pop();
+                } else {
+                    command_line.push('\n');
+                }
+                command_line.push_str(line);
+                (line_no, command_line)
+            }
+            None => (idx + 1, line.to_owned()),
+        };
+
+        if parser::is_incomplete(&command_line) {
+            pending = Some((line_no, command_line));
+        } else {
+            if command_line.ends_with('\n') {
+                command_line.pop();
+            }
+            if !command_line.trim().is_empty() {
+                command_lines.push((line_no, command_line));
+            }
+        }
+    }
+
+    if let Some(incomplete) = pending {
+        command_lines.push(incomplete);
+    }
+
+    command_lines
+}
+
+/// Execute all commands in `source` one after the other and return
+/// the exit status of the last one. Errors are reported on stderr,
+/// prefixed by `name` and the line number, and do not stop the
+/// script. A `return` does.
+pub(crate) fn execute_script(shell: &mut Shell, name: &str, source: &str) -> c_int {
+    let line_offset = shell.line_offset;
+    for (line_no, command_line) in command_lines(source) {
+        shell.line_offset = line_no;
+        let command_line = parser::expand_aliases(&command_line, &shell.aliases);
+        shell.last_status = match parser::parse(&command_line) {
+            Ok((_rest, command_line)) => match execute(shell, &command_line.lists) {
+                Ok(status) => status,
+                Err(e) => {
+                    eprintln!("stsh: {}:{}: {}", name, line_no, e);
+                    1
+                }
+            },
+            Err(e) => {
+                eprintln!("stsh: {}:{}: {}", name, line_no, parser::syntax_error(&e));
+                2
+            }
+        };
+
+        if shell.pending_return.is_some() {
+            break;
+        }
+    }
+    shell.line_offset = line_offset;
+
+    shell.last_status
+}
+
+/// Remove the `#!` line naming the interpreter from the start of
+/// `source`, keeping the line numbers of the rest.
+fn strip_shebang(source: &str) -> &str {
+    if source.starts_with("#!") {
+        &source[source.find('\n').unwrap_or(source.len())..]
+    } else {
+        source
+    }
+}
+
+/// Read the file at `path` and execute it in `shell`.
+pub(crate) fn execute_file(shell: &mut Shell, path: &Path) -> io::Result<c_int> {
+    let source = fs::read_to_string(path)?;
+
+    Ok(execute_script(
+        shell,
+        &path.to_string_lossy(),
+        strip_shebang(&source),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_command_lines() {
+        assert_eq!(
+            super::command_lines("a\n\n  \nb c\n"),
+            vec![(1, "a".to_owned()), (4, "b c".to_owned())]
+        );
+
+        assert_eq!(
+            super::command_lines("a \\\nb\nc \"d\ne\" f\ng"),
+            vec![
+                (1, "a b".to_owned()),
+                (3, "c \"d\ne\" f".to_owned()),
+                (5, "g".to_owned())
+            ]
+        );
+
+        // an unfinished quote at the end of the file is passed on to
+        // the parser, which reports it
+        assert_eq!(
+            super::command_lines("a\n'b\n"),
+            vec![(1, "a".to_owned()), (2, "'b".to_owned())]
+        );
+
+        // quotes in comments do not continue the line
+        assert_eq!(
+            super::command_lines("a # don't\nb"),
+            vec![(1, "a # don't".to_owned()), (2, "b".to_owned())]
+        );
+
+        // a command has to follow `&&`, `||` and `|`
+        assert_eq!(
+            super::command_lines("a &&\n\nb |\n  c\nd"),
+            vec![(1, "a &&\n\nb |\n  c".to_owned()), (5, "d".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_strip_shebang() {
+        let source = "#!/usr/bin/env stsh -x 'a\na\n";
+        assert_eq!(
+            super::command_lines(super::strip_shebang(source)),
+            vec![(2, "a".to_owned())]
+        );
+        assert_eq!(super::strip_shebang("#!"), "");
+        assert_eq!(super::strip_shebang("a\n#!b"), "a\n#!b");
+    }
+}
@@ -0,0 +1,281 @@
+//! Shell state
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libc::{c_int, getpid, getppid, pid_t};
+
+use crate::hash::CommandHash;
+use crate::jobs::Jobs;
+use crate::options::Options;
+use crate::trap::Condition;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Variable {
+    pub value: String,
+    pub exported: bool,
+}
+
+/// State of a running shell instance.
+///
+/// Everything which has to survive from one command line to the next
+/// lives here, e.g. variables and the exit status of the last
+/// command.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Shell {
+    variables: HashMap<String, Variable>,
+    pub last_status: c_int,
+    pub jobs: Jobs,
+    /// Commands found in `PATH` before
+    pub hash: CommandHash,
+    /// Aliases by name, replaced by their values by the parser
+    pub aliases: BTreeMap<String, String>,
+    /// Whether `exit` already warned about stopped jobs
+    pub exit_warned: bool,
+    /// Always `fork` to start commands, even where `posix_spawn` would
+    /// do, for comparison
+    pub force_fork: bool,
+    /// The process id of the last background command, `$!`
+    pub last_background_pid: Option<pid_t>,
+    /// The name of the shell or of the script it executes, `$0`
+    pub name: String,
+    /// The process id of the shell, `$$`, which subshells keep
+    pub pid: pid_t,
+    /// Process substitutions started while expanding the current
+    /// command, by process id and the shell's end of their pipe
+    pub process_substitutions: RefCell<Vec<(pid_t, c_int)>>,
+    /// Processes of the substitutions of background commands, reaped
+    /// once they finish
+    pub substitution_pids: Vec<pid_t>,
+    /// The text matched by the last `=~` of `[[ ]]` and by its
+    /// subexpressions, `${BASH_REMATCH[n]}`
+    pub rematch: Vec<String>,
+    /// The positional parameters `$1`, `$2`, ...
+    pub positional: Vec<String>,
+    /// Number of the line the command line being executed starts on
+    pub line_offset: usize,
+    /// Number of the line of the command being executed, `$LINENO`
+    pub lineno: usize,
+    /// Start of the shell in seconds since the epoch, for `$SECONDS`
+    start_time: u64,
+    /// State of the generator of `$RANDOM`
+    random_state: Cell<u32>,
+    /// Number of files currently executed by `source`
+    pub source_depth: usize,
+    /// Exit status given to `return`, which stops the execution of
+    /// the sourced file
+    pub pending_return: Option<c_int>,
+    pub options: Options,
+    pub interactive: bool,
+    /// Whether pipelines run as jobs in process groups of their own and
+    /// may be stopped, as in interactive shells, but not in subshells
+    pub job_control: bool,
+    /// The terminal handed to the job in the foreground under job
+    /// control
+    pub terminal: Option<c_int>,
+    /// Actions of traps, an empty action ignores the condition
+    pub traps: BTreeMap<Condition, String>,
+    /// Traps whose action is currently executed
+    pub running_traps: Vec<Condition>,
+}
+
+impl Shell {
+    /// Create a shell which knows all variables of the process
+    /// environment, marked as exported.
+    pub(crate) fn from_environment() -> Self {
+        let mut shell = Shell::default();
+        for (name, value) in env::vars() {
+            shell.variables.insert(
+                name,
+                Variable {
+                    value,
+                    exported: true,
+                },
+            );
+        }
+        shell.pid = unsafe { getpid() };
+        shell.set_var("PPID", &unsafe { getppid() }.to_string());
+        shell.start_time = epoch_seconds();
+        shell.seed_random();
+
+        shell
+    }
+
+    /// Seed the generator of `$RANDOM` from the time and the process id,
+    /// also done by subshells to not repeat the numbers of the parent.
+    pub(crate) fn seed_random(&self) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+        self.random_state
+            .set(nanos ^ (unsafe { getpid() } as u32).wrapping_mul(2654435761));
+    }
+
+    /// The next value of `$RANDOM`, between 0 and 32767.
+    pub(crate) fn random(&self) -> u32 {
+        let state = self
+            .random_state
+            .get()
+            .wrapping_mul(1103515245)
+            .wrapping_add(12345);
+        self.random_state.set(state);
+
+        (state >> 16) & 0x7fff
+    }
+
+    /// The value of the variables which change by themselves:
+    /// `RANDOM`, `SECONDS`, `EPOCHSECONDS`, `LINENO` and the elements
+    /// of `BASH_REMATCH`, `BASH_REMATCH` itself being the first one.
+    pub(crate) fn dynamic_var(&self, name: &str) -> Option<String> {
+        match name {
+            "RANDOM" => Some(self.random().to_string()),
+            "SECONDS" => Some((epoch_seconds() - self.start_time).to_string()),
+            "EPOCHSECONDS" => Some(epoch_seconds().to_string()),
+            "LINENO" => Some(self.lineno.to_string()),
+            "BASH_REMATCH" => self.rematch.first().cloned(),
+            _ => {
+                let index = name.strip_prefix("BASH_REMATCH[")?.strip_suffix(']')?;
+                self.rematch.get(index.parse::<usize>().ok()?).cloned()
+            }
+        }
+    }
+
+    pub(crate) fn var(&self, name: &str) -> Option<&str> {
+        self.variables
+            .get(name)
+            .map(|variable| variable.value.as_str())
+    }
+
+    /// All variables, sorted by name.
+    pub(crate) fn vars(&self) -> Vec<(&str, &str)> {
+        let mut vars: Vec<(&str, &str)> = self
+            .variables
+            .iter()
+            .map(|(name, variable)| (name.as_str(), variable.value.as_str()))
+            .collect();
+        vars.sort_unstable();
+
+        vars
+    }
+
+    /// Set a variable, keeping its export attribute. Exported
+    /// variables are written through to the process environment, such
+    /// that child processes inherit them. Setting `PATH` forgets the
+    /// commands found before.
+    pub(crate) fn set_var(&mut self, name: &str, value: &str) {
+        if name == "PATH" {
+            self.hash.clear();
+        }
+
+        match self.variables.get_mut(name) {
+            Some(variable) => {
+                variable.value = value.to_owned();
+                if variable.exported {
+                    env::set_var(name, value);
+                }
+            }
+            None => {
+                self.variables.insert(
+                    name.to_owned(),
+                    Variable {
+                        value: value.to_owned(),
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Set a variable and export it to the process environment, such
+    /// that child processes inherit it.
+    pub(crate) fn export_var(&mut self, name: &str, value: &str) {
+        self.set_var(name, value);
+        if let Some(variable) = self.variables.get_mut(name) {
+            variable.exported = true;
+        }
+        env::set_var(name, value);
+    }
+
+    /// Remove a variable, also from the process environment if it is
+    /// exported.
+    pub(crate) fn unset_var(&mut self, name: &str) {
+        if name == "PATH" {
+            self.hash.clear();
+        }
+
+        if let Some(variable) = self.variables.remove(name) {
+            if variable.exported {
+                env::remove_var(name);
+            }
+        }
+    }
+
+    /// The flags of active options, as shown by `$-`.
+    pub(crate) fn flags(&self) -> String {
+        let mut flags = self.options.flags();
+        if self.interactive {
+            flags.push('i');
+        }
+
+        flags
+    }
+}
+
+fn epoch_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shell;
+
+    #[test]
+    fn test_set_var() {
+        let mut shell = Shell::default();
+        assert_eq!(shell.var("FOO"), None);
+
+        shell.set_var("FOO", "bar");
+        assert_eq!(shell.var("FOO"), Some("bar"));
+
+        shell.set_var("FOO", "baz");
+        assert_eq!(shell.var("FOO"), Some("baz"));
+        assert!(std::env::var_os("FOO").is_none());
+
+        shell.unset_var("FOO");
+        assert_eq!(shell.var("FOO"), None);
+    }
+
+    #[test]
+    fn test_export_var() {
+        let mut shell = Shell::default();
+        shell.export_var("STSH_TEST_EXPORT", "a");
+        assert_eq!(shell.var("STSH_TEST_EXPORT"), Some("a"));
+        assert_eq!(std::env::var("STSH_TEST_EXPORT").as_deref(), Ok("a"));
+
+        shell.set_var("STSH_TEST_EXPORT", "b");
+        assert_eq!(std::env::var("STSH_TEST_EXPORT").as_deref(), Ok("b"));
+
+        shell.unset_var("STSH_TEST_EXPORT");
+        assert!(std::env::var_os("STSH_TEST_EXPORT").is_none());
+    }
+
+    #[test]
+    fn test_dynamic_var() {
+        let shell = Shell::from_environment();
+        assert_eq!(
+            shell.var("PPID"),
+            Some(&*std::os::unix::process::parent_id().to_string())
+        );
+        assert_eq!(shell.dynamic_var("SECONDS").as_deref(), Some("0"));
+        assert_eq!(shell.dynamic_var("FOO"), None);
+        assert_eq!(shell.dynamic_var("BASH_REMATCH"), None);
+
+        let numbers: Vec<u32> = (0..100).map(|_| shell.random()).collect();
+        assert!(numbers.iter().all(|&n| n < 32768));
+        assert!(numbers.iter().any(|&n| n != numbers[0]));
+    }
+}
@@ -4,15 +4,15 @@ use std::error::Error;
 use std::ffi::CStr;
 use std::fmt;
 use std::mem::MaybeUninit;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use libc::{
-    __errno_location, c_int, c_void, sigaction, sigaddset, sigemptyset, sighandler_t, siginfo_t,
-    sigprocmask, sigset_t, strerror,
+    __errno_location, c_int, c_void, close, pipe2, read, sigaction, sigaddset, sigdelset,
+    sigemptyset, sighandler_t, siginfo_t, sigprocmask, sigset_t, sigsuspend, strerror, write,
+    EAGAIN, EINTR, O_CLOEXEC, O_NONBLOCK, SIG_DFL, SIG_IGN,
 };
 
-use crate::execution::catch_background_process;
-
 #[derive(Debug, PartialEq)]
 pub enum SigError {
     Syscall(c_int),
@@ -34,9 +34,189 @@ impl fmt::Display for SigError {
 
 impl Error for SigError {}
 
-pub(crate) extern "C" fn handler(sig: c_int, info: *mut siginfo_t, _gdata: *mut c_void) {
-    if sig == libc::SIGCHLD {
-        catch_background_process(unsafe { (*info).si_pid() });
+/// Signal numbers with their names, without the `SIG` prefix.
+pub(crate) const SIGNALS: [(c_int, &str); 31] = [
+    (libc::SIGHUP, "HUP"),
+    (libc::SIGINT, "INT"),
+    (libc::SIGQUIT, "QUIT"),
+    (libc::SIGILL, "ILL"),
+    (libc::SIGTRAP, "TRAP"),
+    (libc::SIGABRT, "ABRT"),
+    (libc::SIGBUS, "BUS"),
+    (libc::SIGFPE, "FPE"),
+    (libc::SIGKILL, "KILL"),
+    (libc::SIGUSR1, "USR1"),
+    (libc::SIGSEGV, "SEGV"),
+    (libc::SIGUSR2, "USR2"),
+    (libc::SIGPIPE, "PIPE"),
+    (libc::SIGALRM, "ALRM"),
+    (libc::SIGTERM, "TERM"),
+    (libc::SIGSTKFLT, "STKFLT"),
+    (libc::SIGCHLD, "CHLD"),
+    (libc::SIGCONT, "CONT"),
+    (libc::SIGSTOP, "STOP"),
+    (libc::SIGTSTP, "TSTP"),
+    (libc::SIGTTIN, "TTIN"),
+    (libc::SIGTTOU, "TTOU"),
+    (libc::SIGURG, "URG"),
+    (libc::SIGXCPU, "XCPU"),
+    (libc::SIGXFSZ, "XFSZ"),
+    (libc::SIGVTALRM, "VTALRM"),
+    (libc::SIGPROF, "PROF"),
+    (libc::SIGWINCH, "WINCH"),
+    (libc::SIGIO, "IO"),
+    (libc::SIGPWR, "PWR"),
+    (libc::SIGSYS, "SYS"),
+];
+
+/// Signals an interactive shell ignores, such that e.g. Ctrl-C or
+/// Ctrl-Z only affect the foreground job.
+pub(crate) const INTERACTIVE_SIGNALS: [c_int; 5] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// Look up a signal by its number or its name, with or without the
+/// `SIG` prefix and in any case.
+pub(crate) fn signal_number(name: &str) -> Option<c_int> {
+    if let Ok(signum) = name.parse::<c_int>() {
+        return SIGNALS
+            .iter()
+            .find(|(number, _)| *number == signum)
+            .map(|(number, _)| *number);
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNALS
+        .iter()
+        .find(|(_, signame)| *signame == name)
+        .map(|(number, _)| *number)
+}
+
+pub(crate) fn signal_name(signum: c_int) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|(number, _)| *number == signum)
+        .map(|(_, name)| *name)
+}
+
+/// The self-pipe: the signal handler writes the number of each
+/// signal to the write end, the main loop reads them from the read
+/// end, see `take_pending_signals`.
+static SIGNAL_PIPE: [AtomicI32; 2] = [AtomicI32::new(-1), AtomicI32::new(-1)];
+
+/// The last signal other than `SIGCHLD` caught, i.e. one a trap is set
+/// for, or 0, see `take_trapped_signal`.
+static TRAPPED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Create the self-pipe. Signals arriving before are lost. Calling it
+/// again, e.g. in a subshell which must not share the pipe with its
+/// parent, replaces the pipe and closes the previous one.
+pub(crate) fn init_signal_pipe() -> Result<(), SigError> {
+    let mut filedes: [c_int; 2] = [-1, -1];
+    if unsafe { pipe2(filedes.as_mut_ptr(), O_NONBLOCK | O_CLOEXEC) } == -1 {
+        return Err(SigError::Syscall(unsafe { *__errno_location() }));
+    }
+    for (end, fd) in SIGNAL_PIPE.iter().zip(filedes) {
+        let old_fd = end.swap(fd, Ordering::SeqCst);
+        if old_fd != -1 {
+            unsafe { close(old_fd) };
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) extern "C" fn handler(sig: c_int, _info: *mut siginfo_t, _gdata: *mut c_void) {
+    // Only async-signal-safe functions may be called here, see "man 7
+    // signal-safety". The actual work is done by the main loop.
+    if sig != libc::SIGCHLD {
+        TRAPPED_SIGNAL.store(sig, Ordering::SeqCst);
+    }
+    let write_fd = SIGNAL_PIPE[1].load(Ordering::SeqCst);
+    if write_fd != -1 {
+        let saved_errno = unsafe { *__errno_location() };
+        let byte = sig as u8;
+        // if the pipe is full, the signal is pending already
+        unsafe { write(write_fd, &byte as *const u8 as *const c_void, 1) };
+        unsafe { *__errno_location() = saved_errno };
+    }
+}
+
+/// The read end of the self-pipe, e.g. to wait for signals with
+/// `poll`.
+pub(crate) fn signal_pipe_fd() -> c_int {
+    SIGNAL_PIPE[0].load(Ordering::SeqCst)
+}
+
+/// Return the last trapped signal caught since the last call, if any.
+/// Unlike `take_pending_signals`, the signals stay in the self-pipe,
+/// such that their traps are still executed.
+pub(crate) fn take_trapped_signal() -> Option<c_int> {
+    match TRAPPED_SIGNAL.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signum => Some(signum),
+    }
+}
+
+/// Return the signals which arrived since the last call, each one
+/// once, in ascending order.
+pub(crate) fn take_pending_signals() -> Result<Vec<c_int>, SigError> {
+    read_signals(signal_pipe_fd())
+}
+
+/// Read the signal numbers available from the non-blocking pipe
+/// `read_fd` like `take_pending_signals` does.
+pub(crate) fn read_signals(read_fd: c_int) -> Result<Vec<c_int>, SigError> {
+    let mut signals = Vec::new();
+    if read_fd == -1 {
+        return Ok(signals);
+    }
+
+    let mut buf = [0u8; 64];
+    loop {
+        match unsafe { read(read_fd, buf.as_mut_ptr() as *mut c_void, buf.len()) } {
+            -1 => match unsafe { *__errno_location() } {
+                EINTR => continue,
+                EAGAIN => break,
+                error_num => return Err(SigError::Syscall(error_num)),
+            },
+            0 => break,
+            len => signals.extend(buf[..len as usize].iter().map(|&sig| c_int::from(sig))),
+        }
+    }
+    signals.sort_unstable();
+    signals.dedup();
+
+    Ok(signals)
+}
+
+/// What happens when a signal arrives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Disposition {
+    Default,
+    Ignore,
+    /// Record the signal such that the main loop can handle it
+    Catch,
+}
+
+/// Change the disposition of signal `signum`.
+pub(crate) fn set_disposition(signum: c_int, disposition: Disposition) -> Result<(), SigError> {
+    let handler = match disposition {
+        Disposition::Default => SIG_DFL,
+        Disposition::Ignore => SIG_IGN,
+        Disposition::Catch => return install_sighandler(signum, handler),
+    };
+
+    let mut sa = unsafe { MaybeUninit::<sigaction>::zeroed().assume_init() };
+    sa.sa_sigaction = handler;
+    match unsafe { libc::sigaction(signum, &sa, null_mut()) } {
+        -1 => Err(SigError::Syscall(unsafe { *__errno_location() })),
+        _ => Ok(()),
     }
 }
 
@@ -61,6 +241,18 @@ pub(crate) fn unmask_sigchld(chld_set: sigset_t) -> Result<(), SigError> {
     }
 }
 
+/// Wait until a signal is caught, with `SIGCHLD` unblocked meanwhile.
+/// `SIGCHLD` is to be blocked before checking what the wait is for,
+/// such that a child terminating after the check is not missed.
+pub(crate) fn suspend_for_signal() {
+    let mut mask = MaybeUninit::<sigset_t>::uninit();
+    unsafe {
+        sigprocmask(libc::SIG_BLOCK, null(), mask.as_mut_ptr());
+        sigdelset(mask.as_mut_ptr(), libc::SIGCHLD);
+        sigsuspend(mask.as_ptr());
+    }
+}
+
 /// Install a signal handler.
 ///
 /// Registers a signal handler given by `handler` for a signal number
@@ -72,7 +264,7 @@ pub(crate) fn install_sighandler(
     handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void),
 ) -> Result<(), SigError> {
     let sa: sigaction = sigaction {
-        sa_flags: libc::SA_NOCLDSTOP | libc::SA_SIGINFO,
+        sa_flags: libc::SA_SIGINFO,
         sa_sigaction: handler as sighandler_t,
         sa_mask: unsafe { MaybeUninit::<sigset_t>::zeroed().assume_init() },
         sa_restorer: None,
@@ -90,8 +282,48 @@ mod tests {
     use libc::{c_int, c_void, siginfo_t};
     use std::mem::MaybeUninit;
     use std::ptr::{read_volatile, write_volatile};
+    use std::sync::atomic::Ordering;
     use std::sync::Mutex;
 
+    #[test]
+    fn test_signal_number() {
+        assert_eq!(super::signal_number("INT"), Some(libc::SIGINT));
+        assert_eq!(super::signal_number("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(super::signal_number("10"), Some(libc::SIGUSR1));
+        assert_eq!(super::signal_number("NOSUCH"), None);
+        assert_eq!(super::signal_number("0"), None);
+        assert_eq!(super::signal_name(libc::SIGHUP), Some("HUP"));
+    }
+
+    #[test]
+    fn test_signal_pipe() {
+        super::init_signal_pipe().unwrap();
+
+        // once the pipe is replaced, its write end is closed and reading
+        // from a copy of its read end hits end of file
+        let old_read_fd = unsafe { libc::dup(super::SIGNAL_PIPE[0].load(Ordering::SeqCst)) };
+        super::init_signal_pipe().unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(
+            unsafe { libc::read(old_read_fd, buf.as_mut_ptr() as *mut c_void, 1) },
+            0
+        );
+        unsafe { libc::close(old_read_fd) };
+
+        for sig in [libc::SIGUSR2, libc::SIGHUP, libc::SIGUSR2] {
+            super::handler(sig, std::ptr::null_mut(), std::ptr::null_mut());
+        }
+
+        // other tests may cause SIGCHLD
+        let take_pending_signals = || {
+            let mut signals = super::take_pending_signals().unwrap();
+            signals.retain(|&sig| sig != libc::SIGCHLD);
+            signals
+        };
+        assert_eq!(take_pending_signals(), vec![libc::SIGHUP, libc::SIGUSR2]);
+        assert_eq!(take_pending_signals(), vec![]);
+    }
+
     #[test]
     fn test_install_sighandler() {
         assert_eq!(
@@ -0,0 +1,259 @@
+//! Shell invocation and startup files
+
+use std::fmt::{self, Display, Formatter};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::expansion::expand;
+use crate::options::Options;
+use crate::script::execute_file;
+use crate::shell::Shell;
+
+const SYSTEM_PROFILE: &str = "/etc/stsh/profile";
+const USER_PROFILE: &str = ".stsh_profile";
+const USER_RC: &str = ".stshrc";
+
+#[derive(Debug, PartialEq)]
+pub enum InvocationError {
+    InvalidOption(String),
+    InvalidOptionName(String),
+}
+
+impl Display for InvocationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InvocationError::InvalidOption(option) => write!(f, "{}: invalid option", option),
+            InvocationError::InvalidOptionName(name) => {
+                write!(f, "{}: invalid option name", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvocationError {}
+
+/// How the shell was started.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Invocation {
+    pub login: bool,
+    pub interactive: bool,
+    pub norc: bool,
+    pub noprofile: bool,
+    /// Options given like to `set`, e.g. `-e` or `-o pipefail`
+    pub options: Options,
+    pub script: Option<String>,
+    /// Arguments following the script, which become the positional
+    /// parameters
+    pub args: Vec<String>,
+}
+
+/// Evaluate the command line arguments `args`, including the program
+/// name. A login shell is either started with `-l`/`--login` or with
+/// a program name starting with `-`. Without a script, the shell is
+/// interactive if `stdin_is_tty`. All options of `set` are accepted
+/// as well.
+pub(crate) fn parse_arguments(
+    args: &[String],
+    stdin_is_tty: bool,
+) -> Result<Invocation, InvocationError> {
+    let mut invocation = Invocation {
+        login: args.first().is_some_and(|name| name.starts_with('-')),
+        ..Invocation::default()
+    };
+    let mut force_interactive = false;
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--login" => invocation.login = true,
+            "--norc" => invocation.norc = true,
+            "--noprofile" => invocation.noprofile = true,
+            "--" => break,
+            option if option.starts_with("--") => {
+                return Err(InvocationError::InvalidOption(option.to_owned()))
+            }
+            option if (option.starts_with('-') || option.starts_with('+')) && option.len() > 1 => {
+                let value = option.starts_with('-');
+                for flag in option[1..].chars() {
+                    match flag {
+                        'l' if value => invocation.login = true,
+                        'i' if value => force_interactive = true,
+                        'o' => {
+                            let name = args
+                                .next()
+                                .ok_or_else(|| InvocationError::InvalidOption(option.to_owned()))?;
+                            if !invocation.options.set(name, value) {
+                                return Err(InvocationError::InvalidOptionName(name.to_owned()));
+                            }
+                        }
+                        _ => {
+                            if !invocation.options.set_flag(flag, value) {
+                                return Err(InvocationError::InvalidOption(format!(
+                                    "{}{}",
+                                    &option[..1],
+                                    flag
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+            script => {
+                invocation.script = Some(script.to_owned());
+                break;
+            }
+        }
+    }
+
+    if invocation.script.is_none() {
+        invocation.script = args.next().cloned();
+    }
+    invocation.args = args.cloned().collect();
+    invocation.interactive = force_interactive || (invocation.script.is_none() && stdin_is_tty);
+
+    Ok(invocation)
+}
+
+fn source_startup_file(shell: &mut Shell, path: &Path) {
+    if let Err(e) = execute_file(shell, path) {
+        if e.kind() != ErrorKind::NotFound {
+            eprintln!("stsh: {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Execute the startup files appropriate for `invocation`.
+///
+/// Login shells read `/etc/stsh/profile` and `~/.stsh_profile`,
+/// unless `--noprofile` is given. Interactive shells then read the
+/// file named by `$ENV`, after parameter expansion, or `~/.stshrc`
+/// if `ENV` is not set, unless `--norc` is given.
+pub(crate) fn run_startup_files(shell: &mut Shell, invocation: &Invocation) {
+    let home = shell.var("HOME").map(PathBuf::from);
+
+    if invocation.login && !invocation.noprofile {
+        source_startup_file(shell, Path::new(SYSTEM_PROFILE));
+        if let Some(ref home) = home {
+            source_startup_file(shell, &home.join(USER_PROFILE));
+        }
+    }
+
+    if invocation.interactive && !invocation.norc {
+        match shell.var("ENV").map(str::to_owned) {
+            Some(env) if !env.is_empty() => match expand(shell, &env) {
+                Ok(env) => source_startup_file(shell, Path::new(&env)),
+                Err(e) => eprintln!("stsh: ENV: {}", e),
+            },
+            _ => {
+                if let Some(ref home) = home {
+                    source_startup_file(shell, &home.join(USER_RC));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invocation, InvocationError};
+    use crate::options::Options;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh"]), true),
+            Ok(Invocation {
+                interactive: true,
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["-stsh"]), true),
+            Ok(Invocation {
+                login: true,
+                interactive: true,
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "-l", "--norc", "--noprofile"]), false),
+            Ok(Invocation {
+                login: true,
+                norc: true,
+                noprofile: true,
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "script", "-l"]), true),
+            Ok(Invocation {
+                script: Some("script".to_owned()),
+                args: vec!["-l".to_owned()],
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "-i", "--", "-script"]), false),
+            Ok(Invocation {
+                interactive: true,
+                script: Some("-script".to_owned()),
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "--rc"]), true),
+            Err(InvocationError::InvalidOption("--rc".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_set_options() {
+        assert_eq!(
+            super::parse_arguments(
+                &args(&["stsh", "-eu", "-o", "pipefail", "+u", "script"]),
+                false
+            ),
+            Ok(Invocation {
+                options: Options {
+                    errexit: true,
+                    pipefail: true,
+                    ..Options::default()
+                },
+                script: Some("script".to_owned()),
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "-xil"]), false),
+            Ok(Invocation {
+                login: true,
+                interactive: true,
+                options: Options {
+                    xtrace: true,
+                    ..Options::default()
+                },
+                ..Invocation::default()
+            })
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "+eq"]), false),
+            Err(InvocationError::InvalidOption("+q".to_owned()))
+        );
+
+        assert_eq!(
+            super::parse_arguments(&args(&["stsh", "-o", "pipefall"]), false),
+            Err(InvocationError::InvalidOptionName("pipefall".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! Helpers shared by the unit tests
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::shell::Shell;
+
+/// A directory for the files of one test, removed with everything in
+/// it when dropped, also if the test fails.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory, unique to the test `name` and this
+    /// process.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("stsh-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Run `test` once for each way the shell starts external commands,
+/// `posix_spawn` and `fork`, with a fresh shell which finds the usual
+/// commands in `PATH`.
+pub(crate) fn for_each_backend(mut test: impl FnMut(&mut Shell)) {
+    for force_fork in [false, true] {
+        let mut shell = Shell::default();
+        shell.set_var("PATH", "/usr/bin:/bin");
+        shell.force_fork = force_fork;
+
+        test(&mut shell);
+    }
+}
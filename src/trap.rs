@@ -0,0 +1,218 @@
+//! Traps, i.e. commands executed on signals and other conditions
+//!
+//! Signal handlers only record that a signal arrived, see
+//! `sig::handler`. The actions of traps are executed afterwards by the
+//! main loop, between commands.
+
+use std::ptr::null_mut;
+
+use libc::c_int;
+
+use crate::jobs::report_finished_jobs;
+use crate::script::execute_script;
+use crate::shell::Shell;
+use crate::sig::{
+    self, set_disposition, take_pending_signals, Disposition, SigError, INTERACTIVE_SIGNALS,
+};
+
+/// A condition a trap can be set for.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Condition {
+    /// The shell exits
+    Exit,
+    /// A command fails, in the cases errexit would apply
+    Err,
+    /// Before each pipeline
+    Debug,
+    /// A sourced file finishes
+    Return,
+    Signal(c_int),
+}
+
+impl Condition {
+    /// Parse a condition given to `trap`, i.e. a signal name or
+    /// number, or one of the pseudo signals `EXIT` (also `0`), `ERR`,
+    /// `DEBUG` and `RETURN`.
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "0" | "EXIT" => Some(Condition::Exit),
+            "ERR" => Some(Condition::Err),
+            "DEBUG" => Some(Condition::Debug),
+            "RETURN" => Some(Condition::Return),
+            _ => sig::signal_number(name).map(Condition::Signal),
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        match self {
+            Condition::Exit => "EXIT".to_owned(),
+            Condition::Err => "ERR".to_owned(),
+            Condition::Debug => "DEBUG".to_owned(),
+            Condition::Return => "RETURN".to_owned(),
+            Condition::Signal(signum) => match sig::signal_name(*signum) {
+                Some(name) => format!("SIG{}", name),
+                None => signum.to_string(),
+            },
+        }
+    }
+}
+
+/// Signals the shell handles itself, their traps are run in addition.
+fn is_shell_signal(signum: c_int) -> bool {
+    signum == libc::SIGCHLD
+}
+
+/// The disposition of `signum` without a trap: interactive shells
+/// ignore some signals.
+fn default_disposition(shell: &Shell, signum: c_int) -> Disposition {
+    if shell.interactive && INTERACTIVE_SIGNALS.contains(&signum) {
+        Disposition::Ignore
+    } else {
+        Disposition::Default
+    }
+}
+
+/// Set the trap for `condition` to `action`. An empty action ignores
+/// the condition, `None` resets it to the default.
+pub(crate) fn set_trap(
+    shell: &mut Shell,
+    condition: Condition,
+    action: Option<&str>,
+) -> Result<(), SigError> {
+    if let Condition::Signal(signum) = condition {
+        let disposition = match action {
+            _ if is_shell_signal(signum) => Disposition::Catch,
+            Some("") => Disposition::Ignore,
+            Some(_) => Disposition::Catch,
+            None => default_disposition(shell, signum),
+        };
+        set_disposition(signum, disposition)?;
+    }
+
+    match action {
+        Some(action) => shell.traps.insert(condition, action.to_owned()),
+        None => shell.traps.remove(&condition),
+    };
+
+    Ok(())
+}
+
+/// Execute the action of the trap for `condition`, if any. Traps do
+/// not trigger themselves recursively and leave `$?` unchanged.
+pub(crate) fn run_trap(shell: &mut Shell, condition: Condition) {
+    let action = match shell.traps.get(&condition) {
+        Some(action) if !action.is_empty() => action.clone(),
+        _ => return,
+    };
+    if shell.running_traps.contains(&condition) {
+        return;
+    }
+
+    let status = shell.last_status;
+    shell.running_traps.push(condition);
+    execute_script(shell, "trap", &action);
+    shell.running_traps.pop();
+    shell.last_status = status;
+}
+
+/// Handle all signals which arrived in the meantime: reap finished
+/// jobs and process substitutions on `SIGCHLD`, reporting the jobs
+/// right away with the notify option, and execute the traps.
+pub(crate) fn handle_pending_signals(shell: &mut Shell) {
+    match take_pending_signals() {
+        Ok(signals) => handle_signals(shell, &signals),
+        Err(e) => eprintln!("stsh: {}", e),
+    }
+}
+
+/// Handle `signals` like `handle_pending_signals`, for those which
+/// were taken from the self-pipe elsewhere.
+pub(crate) fn handle_signals(shell: &mut Shell, signals: &[c_int]) {
+    for &signum in signals {
+        if signum == libc::SIGCHLD {
+            shell.jobs.update();
+            shell
+                .substitution_pids
+                .retain(|&pid| unsafe { libc::waitpid(pid, null_mut(), libc::WNOHANG) } == 0);
+            if shell.options.notify {
+                report_finished_jobs(shell);
+            }
+        }
+        run_trap(shell, Condition::Signal(signum));
+    }
+}
+
+/// Reset all traps which are not ignored, as done when entering a
+/// subshell.
+pub(crate) fn reset_traps(shell: &mut Shell) {
+    let conditions: Vec<Condition> = shell
+        .traps
+        .iter()
+        .filter(|(_, action)| !action.is_empty())
+        .map(|(condition, _)| *condition)
+        .collect();
+
+    for condition in conditions {
+        if let Condition::Signal(signum) = condition {
+            if !is_shell_signal(signum) {
+                let _ = set_disposition(signum, default_disposition(shell, signum));
+            }
+        }
+        shell.traps.remove(&condition);
+    }
+}
+
+/// The signals an interactive shell ignores, which child processes
+/// get the default disposition for, unless a trap ignores them.
+pub(crate) fn signals_to_restore(shell: &Shell) -> Vec<c_int> {
+    if !shell.interactive {
+        return Vec::new();
+    }
+
+    INTERACTIVE_SIGNALS
+        .iter()
+        .copied()
+        .filter(|&signum| shell.traps.get(&Condition::Signal(signum)) != Some(&String::new()))
+        .collect()
+}
+
+/// Restore the default dispositions of `signals_to_restore`. This is
+/// done in child processes, such that they can be interrupted.
+pub(crate) fn restore_signals(shell: &Shell) {
+    for signum in signals_to_restore(shell) {
+        let _ = set_disposition(signum, Disposition::Default);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use crate::shell::Shell;
+
+    #[test]
+    fn test_parse_condition() {
+        assert_eq!(Condition::parse("EXIT"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("0"), Some(Condition::Exit));
+        assert_eq!(Condition::parse("err"), Some(Condition::Err));
+        assert_eq!(
+            Condition::parse("SIGINT"),
+            Some(Condition::Signal(libc::SIGINT))
+        );
+        assert_eq!(Condition::parse("NOSUCH"), None);
+        assert_eq!(Condition::Signal(libc::SIGUSR1).name(), "SIGUSR1");
+    }
+
+    #[test]
+    fn test_run_trap() {
+        let mut shell = Shell::default();
+        super::set_trap(&mut shell, Condition::Err, Some("set -- $?")).unwrap();
+        shell.last_status = 3;
+
+        super::run_trap(&mut shell, Condition::Err);
+        assert_eq!(shell.positional, vec!["3".to_owned()]);
+        assert_eq!(shell.last_status, 3);
+
+        super::set_trap(&mut shell, Condition::Err, None).unwrap();
+        assert!(shell.traps.is_empty());
+    }
+}